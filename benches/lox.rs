@@ -0,0 +1,102 @@
+// Times the Lox programs in benches/suite and the single-feature programs
+// in benches/micro. Each program runs through `rlox --time`, which reports
+// how long every phase took. Run with `cargo bench --bench lox [name]`.
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant},
+};
+
+const DIRS: [&str; 2] = ["benches/suite", "benches/micro"];
+
+// the best of several runs is least affected by other load on the machine
+const RUNS: usize = 5;
+
+const PHASES: [&str; 5] = ["scan", "parse", "resolve", "optimize", "execute"];
+
+struct Timing {
+    total: Duration,
+    // milliseconds for each of PHASES, as printed by rlox
+    phases: Vec<f64>,
+}
+
+fn main() {
+    // cargo passes --bench; any other argument picks programs by name
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+
+    print!("{:<24}", "program");
+    for phase in PHASES.iter().chain(&["total"]) {
+        print!("{:>10}", phase);
+    }
+    println!();
+
+    for dir in DIRS {
+        for path in programs(dir) {
+            let name = format!(
+                "{}/{}",
+                dir.trim_start_matches("benches/"),
+                path.file_stem().unwrap().to_string_lossy()
+            );
+            if filter
+                .as_ref()
+                .is_some_and(|filter| !name.contains(filter.as_str()))
+            {
+                continue;
+            }
+
+            let best = (0..RUNS)
+                .map(|_| run(&path))
+                .min_by_key(|t| t.total)
+                .unwrap();
+
+            print!("{:<24}", name);
+            for ms in &best.phases {
+                print!("{:>10.3}", ms);
+            }
+            println!("{:>10.3}", best.total.as_secs_f64() * 1000.0);
+        }
+    }
+}
+
+fn programs(dir: &str) -> Vec<PathBuf> {
+    let dir: PathBuf = [env!("CARGO_MANIFEST_DIR"), dir].iter().collect();
+    let mut programs: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .expect("Failed to read benches")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    programs.sort();
+    programs
+}
+
+fn run(path: &Path) -> Timing {
+    let start = Instant::now();
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .arg("--time")
+        .arg(path)
+        .output()
+        .expect("Failed to execute process");
+    let total = start.elapsed();
+
+    let stderr = String::from_utf8(output.stderr).expect("Failed to convert to string");
+    assert!(
+        output.status.success(),
+        "{} failed:\n{}",
+        path.display(),
+        stderr
+    );
+
+    // each phase is reported on its own line as "<phase> <ms> ms"
+    let phases = PHASES
+        .iter()
+        .map(|phase| {
+            stderr
+                .lines()
+                .filter_map(|line| line.strip_prefix(phase))
+                .find_map(|rest| rest.trim().trim_end_matches("ms").trim().parse().ok())
+                .unwrap_or_else(|| panic!("No {} time for {}", phase, path.display()))
+        })
+        .collect();
+
+    Timing { total, phases }
+}
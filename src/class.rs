@@ -1,41 +1,206 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use crate::{
+    env::Env,
     error::{Result, RloxError},
+    expr::Expr,
     functions::Callable,
-    object::Object,
-    tokens::Token,
+    interpreter::Interpreter,
+    object::{self, Object},
+    shape::{Cached, InlineCache, Shape},
+    symbol::Symbol,
+    tokens::{Literal, Token, TokenType},
 };
 
+// Private members belong to the class or trait declaring them, which is
+// identified by a number bound to OWNER in the environment of its methods
+pub(crate) fn next_owner() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
 #[derive(Debug, Copy, Clone)]
 pub(crate) enum ClassType {
     None,
     Class,
     SubClass,
+    Trait,
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct LoxClass {
     name: String,
+    owner: usize,
     parent: Option<Rc<LoxClass>>,
-    methods: HashMap<String, Callable>,
+    traits: Vec<Rc<LoxTrait>>,
+    methods: HashMap<Symbol, Callable>,
+    // methods declared abstract in the class body
+    abstracts: Vec<Symbol>,
+    fields: FieldDecls,
+    // the shape of a new instance, before it has any fields
+    shape: Rc<Shape>,
+}
+
+// The field declarations of a class body, evaluated for every new instance
+#[derive(Debug, Clone)]
+pub(crate) struct FieldDecls {
+    // the environment of the class's methods, which initializers also run in
+    env: Rc<Env>,
+    fields: Vec<(Token, Option<Expr>)>,
+}
+
+impl FieldDecls {
+    pub(crate) fn new(env: &Rc<Env>, fields: Vec<(Token, Option<Expr>)>) -> Self {
+        Self {
+            env: Rc::clone(env),
+            fields,
+        }
+    }
 }
 
 impl LoxClass {
     pub(crate) fn new(
         name: String,
+        owner: usize,
         parent: Option<Rc<LoxClass>>,
-        methods: HashMap<String, Callable>,
+        traits: Vec<Rc<LoxTrait>>,
+        methods: HashMap<Symbol, Callable>,
+        abstracts: Vec<Symbol>,
+        fields: FieldDecls,
     ) -> Self {
         Self {
             name,
+            owner,
             parent,
+            traits,
             methods,
+            abstracts,
+            fields,
+            shape: Rc::new(Shape::default()),
+        }
+    }
+
+    // Sets the declared fields of a new instance, starting with the superclass's.
+    // Initializers can use 'this' to read fields set before them.
+    pub(crate) fn init_fields(&self, interpreter: &Interpreter, inst: &LoxInstance) -> Result<()> {
+        if let Some(ref p) = self.parent {
+            p.init_fields(interpreter, inst)?;
+        }
+
+        if self.fields.fields.is_empty() {
+            return Ok(());
+        }
+
+        let env = Env::from(&self.fields.env);
+        THIS.with(|this| env.define(this, Object::Instance(inst.clone())))?;
+        let mut scope = interpreter.with_env(env);
+
+        for (name, init) in &self.fields.fields {
+            let val = match init {
+                Some(init) => init.accept(&mut scope)?,
+                None => Object::Literal(Literal::Nil),
+            };
+
+            match name.token_type {
+                TokenType::Private => inst.set_private(self.owner, name, val)?,
+                _ => inst.set(name, val)?,
+            };
+        }
+
+        Ok(())
+    }
+
+    // Only classes declaring abstract methods themselves may leave
+    // inherited ones unimplemented
+    pub(crate) fn is_abstract(&self) -> bool {
+        !self.abstracts.is_empty()
+    }
+
+    // Abstract methods of the class, its traits and its ancestors which
+    // have no implementation, paired with where they were declared
+    pub(crate) fn unimplemented(&self) -> Vec<(String, String)> {
+        let mut declared = Vec::new();
+        self.collect_abstracts(&mut declared);
+
+        let mut missing: Vec<_> = declared
+            .into_iter()
+            .filter(|(method, _)| self.find_method(Symbol::intern(method)).is_none())
+            .collect();
+        missing.sort();
+        missing.dedup_by(|a, b| a.0 == b.0);
+
+        missing
+    }
+
+    fn collect_abstracts(&self, declared: &mut Vec<(String, String)>) {
+        for method in &self.abstracts {
+            declared.push((method.to_string(), self.name.clone()));
+        }
+
+        for t in &self.traits {
+            for method in &t.abstracts {
+                declared.push((method.to_string(), t.name.clone()));
+            }
+        }
+
+        if let Some(ref p) = self.parent {
+            p.collect_abstracts(declared);
         }
     }
 
-    pub(crate) fn find_method(&self, name: &str) -> Option<&Callable> {
-        if let Some(m) = self.methods.get(name) {
+    // true for the class itself and any of its descendants
+    pub(crate) fn is_subclass_of(&self, other: &Rc<LoxClass>) -> bool {
+        if std::ptr::eq(self, other.as_ref()) {
+            return true;
+        }
+
+        self.parent
+            .as_ref()
+            .is_some_and(|p| p.is_subclass_of(other))
+    }
+
+    // Every public method an instance can call, including inherited ones
+    pub(crate) fn method_names(&self) -> Vec<String> {
+        let mut names = match self.parent {
+            Some(ref p) => p.method_names(),
+            None => Vec::new(),
+        };
+
+        let own = self.methods.keys();
+        let mixed = self.traits.iter().flat_map(|t| t.method_names());
+        names.extend(
+            own.chain(mixed)
+                .filter(|m| !m.starts_with('#'))
+                .map(|m| m.to_string()),
+        );
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // A #method of the class or trait with the given owner
+    fn find_private(&self, owner: usize, name: Symbol) -> Option<&Callable> {
+        if self.owner == owner {
+            return self.methods.get(&name);
+        } else if let Some(t) = self.traits.iter().find(|t| t.owner == owner) {
+            return t.methods.get(&name);
+        }
+
+        self.parent.as_ref()?.find_private(owner, name)
+    }
+
+    // The class's own methods come first, then its traits in the order
+    // they were listed and then the superclass
+    pub(crate) fn find_method(&self, name: Symbol) -> Option<&Callable> {
+        if let Some(m) = self.methods.get(&name) {
+            return Some(m);
+        } else if let Some(m) = self.traits.iter().find_map(|t| t.methods.get(&name)) {
             return Some(m);
         } else if let Some(ref p) = self.parent {
             return p.find_method(name);
@@ -44,68 +209,308 @@ impl LoxClass {
         None
     }
 }
+
+// area (from Shape), name (from Named)
+pub(crate) fn describe_unimplemented(missing: &[(String, String)]) -> String {
+    missing
+        .iter()
+        .map(|(method, owner)| format!("{} (from {})", method, owner))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 impl std::fmt::Display for LoxClass {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.name)
     }
 }
 
+// A set of methods which classes can mix in with 'with'
 #[derive(Debug, Clone)]
-pub(crate) struct LoxInstance {
+pub(crate) struct LoxTrait {
+    name: String,
+    owner: usize,
+    methods: HashMap<Symbol, Callable>,
+    // methods classes using the trait must implement
+    abstracts: Vec<Symbol>,
+}
+
+impl LoxTrait {
+    pub(crate) fn new(
+        name: String,
+        owner: usize,
+        methods: HashMap<Symbol, Callable>,
+        abstracts: Vec<Symbol>,
+    ) -> Self {
+        Self {
+            name,
+            owner,
+            methods,
+            abstracts,
+        }
+    }
+
+    pub(crate) fn has_method(&self, name: Symbol) -> bool {
+        self.methods.contains_key(&name)
+    }
+
+    pub(crate) fn method_names(&self) -> impl Iterator<Item = &Symbol> {
+        self.methods.keys()
+    }
+}
+
+impl std::fmt::Display for LoxTrait {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+// A handle to an instance; clones refer to the same instance
+#[derive(Debug, Clone)]
+pub(crate) struct LoxInstance(Rc<Instance>);
+
+#[derive(Debug)]
+struct Instance {
     class: Rc<LoxClass>,
-    fields: Rc<RefCell<HashMap<String, Object>>>,
+    fields: RefCell<Fields>,
+    // #fields, keyed by the owner of the method which set them
+    private: RefCell<HashMap<(usize, Symbol), Object>>,
+    // set by freeze(); fields can no longer be set
+    frozen: Cell<bool>,
 }
 
 impl LoxInstance {
     pub(crate) fn new(class: &Rc<LoxClass>) -> Self {
-        Self {
+        Self(Rc::new(Instance {
             class: Rc::clone(class),
-            fields: Rc::new(RefCell::new(HashMap::new())),
+            fields: RefCell::new(Fields {
+                shape: Rc::clone(&class.shape),
+                values: Vec::new(),
+            }),
+            private: RefCell::new(HashMap::new()),
+            frozen: Cell::new(false),
+        }))
+    }
+
+    pub(crate) fn freeze(&self) {
+        self.0.frozen.set(true);
+    }
+
+    pub(crate) fn class(&self) -> &Rc<LoxClass> {
+        &self.0.class
+    }
+
+    // Names of the public fields, sorted so they print predictably
+    pub(crate) fn field_names(&self) -> Vec<String> {
+        let fields = self.0.fields.borrow();
+        let mut names: Vec<String> = fields.shape.names().iter().map(|k| k.to_string()).collect();
+        names.sort();
+        names
+    }
+
+    // Moves out the fields when this is the last reference to the instance
+    pub(crate) fn release(self, values: &mut Vec<Object>) {
+        if let Ok(mut inst) = Rc::try_unwrap(self.0) {
+            values.append(&mut inst.fields.get_mut().values);
+            values.extend(inst.private.get_mut().drain().map(|(_, val)| val));
         }
     }
 
+    // Values of the public fields, in the order they were first set
+    pub(crate) fn field_values(&self) -> Vec<Object> {
+        self.0.fields.borrow().values.clone()
+    }
+
     pub(crate) fn get(&self, field: &Token) -> Result<Object> {
-        if let Some(obj) = self.fields.borrow().get(&field.lexeme) {
-            return Ok(obj.clone());
+        self.get_cached(field, None)
+    }
+
+    pub(crate) fn get_cached(&self, field: &Token, cache: Option<&InlineCache>) -> Result<Object> {
+        match self.property(field, cache)? {
+            Property::Value(val) => Ok(val),
+            Property::Method(method) => Ok(Object::func(method.bind(self))),
         }
+    }
 
-        if let Some(method) = self.class.find_method(field.lexeme.as_ref()) {
-            return Ok(Object::Func(method.bind(self)));
+    // Fields are found through the instance's shape, then methods through
+    // the class. With a cache from the access site, either can be skipped
+    // when the shape is one seen there before. Methods are left unbound.
+    pub(crate) fn property(&self, field: &Token, cache: Option<&InlineCache>) -> Result<Property> {
+        if field.token_type == TokenType::Private {
+            return Err(LoxInstance::private_err(field));
+        }
+
+        let fields = self.0.fields.borrow();
+        match cache.and_then(|cache| cache.lookup(&fields.shape)) {
+            Some(Cached::Field(slot)) => return Ok(Property::Value(fields.values[slot].clone())),
+            Some(Cached::Method(method)) => return Ok(Property::Method(method)),
+            _ => (),
+        }
+
+        if let Some(slot) = fields.shape.slot(field.lexeme) {
+            if let Some(cache) = cache {
+                cache.fill(&fields.shape, Cached::Field(slot));
+            }
+            return Ok(Property::Value(fields.values[slot].clone()));
+        }
+
+        if let Some(method) = self.0.class.find_method(field.lexeme) {
+            if let Some(cache) = cache {
+                cache.fill(&fields.shape, Cached::Method(method.clone()));
+            }
+            return Ok(Property::Method(method.clone()));
         }
 
         Err(RloxError::Runtime(
             field.line,
             format!("Undefined property {}", field.lexeme),
-            field.lexeme.to_owned(),
+            field.lexeme.to_string(),
         ))
     }
 
-    // Lox allows freely creating new fields
+    // Lox allows freely creating new fields, unless the instance is frozen
     pub(crate) fn set(&self, field: &Token, val: Object) -> Result<Object> {
-        self.fields
+        self.set_cached(field, val, None)
+    }
+
+    pub(crate) fn set_cached(
+        &self,
+        field: &Token,
+        val: Object,
+        cache: Option<&InlineCache>,
+    ) -> Result<Object> {
+        if field.token_type == TokenType::Private {
+            return Err(LoxInstance::private_err(field));
+        }
+
+        self.check_frozen(field)?;
+
+        let mut fields = self.0.fields.borrow_mut();
+        let slot = match cache.and_then(|cache| cache.lookup(&fields.shape)) {
+            Some(Cached::Field(slot)) => Some(slot),
+            Some(Cached::Transition(next)) => {
+                fields.shape = next;
+                None
+            }
+            _ => match fields.shape.slot(field.lexeme) {
+                Some(slot) => {
+                    if let Some(cache) = cache {
+                        cache.fill(&fields.shape, Cached::Field(slot));
+                    }
+                    Some(slot)
+                }
+                // a new field moves the instance on to the next shape
+                None => {
+                    let next = fields.shape.with(field.lexeme);
+                    if let Some(cache) = cache {
+                        cache.fill(&fields.shape, Cached::Transition(Rc::clone(&next)));
+                    }
+                    fields.shape = next;
+                    None
+                }
+            },
+        };
+
+        match slot {
+            Some(slot) => fields.values[slot] = val.clone(),
+            None => fields.values.push(val.clone()),
+        }
+        Ok(val)
+    }
+}
+
+impl LoxInstance {
+    pub(crate) fn get_private(&self, owner: usize, field: &Token) -> Result<Object> {
+        let key = (owner, field.lexeme);
+        if let Some(obj) = self.0.private.borrow().get(&key) {
+            return Ok(obj.clone());
+        }
+
+        if let Some(method) = self.0.class.find_private(owner, field.lexeme) {
+            return Ok(Object::func(method.bind(self)));
+        }
+
+        Err(RloxError::Runtime(
+            field.line,
+            format!("Undefined private member {}", field.lexeme),
+            field.lexeme.to_string(),
+        ))
+    }
+
+    pub(crate) fn set_private(&self, owner: usize, field: &Token, val: Object) -> Result<Object> {
+        self.check_frozen(field)?;
+
+        self.0
+            .private
             .borrow_mut()
-            .insert(field.lexeme.clone(), val.clone());
+            .insert((owner, field.lexeme), val.clone());
         Ok(val)
     }
+
+    fn check_frozen(&self, field: &Token) -> Result<()> {
+        if self.0.frozen.get() {
+            return Err(RloxError::Runtime(
+                field.line,
+                "Cannot set a field of a frozen instance".to_string(),
+                field.lexeme.to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn private_err(field: &Token) -> RloxError {
+        RloxError::Runtime(
+            field.line,
+            "Private members are only accessible inside their class".to_string(),
+            field.lexeme.to_string(),
+        )
+    }
+}
+
+// A property of an instance: a field's value or one of its class's methods
+pub(crate) enum Property {
+    Value(Object),
+    Method(Callable),
+}
+
+// fields are freed in a loop, see object::free
+impl Drop for Instance {
+    fn drop(&mut self) {
+        let mut values = std::mem::take(&mut self.fields.get_mut().values);
+        values.extend(self.private.get_mut().drain().map(|(_, val)| val));
+        object::free(values);
+    }
+}
+
+// An instance's public fields, in the slots given by its shape
+#[derive(Debug)]
+struct Fields {
+    shape: Rc<Shape>,
+    values: Vec<Object>,
 }
 
 impl std::fmt::Display for LoxInstance {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} instance", self.class)
+        write!(f, "{} instance", self.0.class)
     }
 }
 
-use lazy_static::lazy_static;
-
-lazy_static! {
-    pub(crate) static ref THIS: Token = Token {
+// Tokens hold reference counted literals and so are not Sync
+thread_local! {
+    pub(crate) static THIS: Token = Token {
         token_type: crate::tokens::TokenType::This,
-        lexeme: "this".to_string(),
+        lexeme: Symbol::intern("this"),
         ..Token::default()
     };
-    pub(crate) static ref SUPER: Token = Token {
+    pub(crate) static SUPER: Token = Token {
         token_type: crate::tokens::TokenType::Super,
-        lexeme: "super".to_string(),
+        lexeme: Symbol::intern("super"),
+        ..Token::default()
+    };
+    pub(crate) static OWNER: Token = Token {
+        token_type: crate::tokens::TokenType::Private,
+        lexeme: Symbol::intern("#owner"),
         ..Token::default()
     };
 }
@@ -0,0 +1,102 @@
+use std::rc::Rc;
+
+use crate::{
+    error::{Result, RloxError},
+    functions::{Callable, NativeFunction},
+    object::Object,
+    tokens::{Literal, Token},
+};
+
+// Properties of every enum, which no variant may be named after. A variant's
+// own name and ordinal are read from the variant, so they cannot clash.
+pub(crate) const MEMBERS: &[&str] = &["values"];
+
+// enum Color { Red, Green, Blue }
+#[derive(Debug)]
+pub(crate) struct LoxEnum {
+    name: String,
+    variants: Vec<Rc<Variant>>,
+}
+
+// Each variant is created once, so variants compare by identity
+#[derive(Debug)]
+pub(crate) struct Variant {
+    // the name of the enum the variant belongs to
+    owner: String,
+    name: String,
+    ordinal: usize,
+}
+
+impl LoxEnum {
+    pub(crate) fn new(name: &Token, variants: &[Token]) -> Self {
+        let variants = variants
+            .iter()
+            .enumerate()
+            .map(|(ordinal, id)| {
+                Rc::new(Variant {
+                    owner: name.lexeme.to_string(),
+                    name: id.lexeme.to_string(),
+                    ordinal,
+                })
+            })
+            .collect();
+
+        Self {
+            name: name.lexeme.to_string(),
+            variants,
+        }
+    }
+
+    // Color.Red or Color.values
+    pub(crate) fn get(this: &Rc<Self>, prop: &Token) -> Result<Object> {
+        if let Some(v) = this
+            .variants
+            .iter()
+            .find(|v| prop.lexeme == v.name.as_str())
+        {
+            return Ok(Object::Variant(Rc::clone(v)));
+        }
+
+        if prop.lexeme == "values" {
+            let this = Rc::clone(this);
+            let values = NativeFunction::new("values", 0, move |_, _, _| {
+                let variants = this.variants.iter().map(|v| Object::Variant(Rc::clone(v)));
+                Ok(Object::list(variants.collect()))
+            });
+
+            return Ok(Object::func(Callable::Native(values)));
+        }
+
+        Err(RloxError::Runtime(
+            prop.line,
+            format!("{} has no variant {}", this.name, prop.lexeme),
+            prop.lexeme.to_string(),
+        ))
+    }
+}
+
+impl Variant {
+    pub(crate) fn get(&self, prop: &Token) -> Result<Object> {
+        match prop.lexeme.as_str() {
+            "name" => Ok(Object::string(self.name.clone())),
+            "ordinal" => Ok(Object::Literal(Literal::Integer(self.ordinal as i64))),
+            _ => Err(RloxError::Runtime(
+                prop.line,
+                format!("Undefined property {}", prop.lexeme),
+                prop.lexeme.to_string(),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for LoxEnum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.owner, self.name)
+    }
+}
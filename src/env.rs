@@ -1,14 +1,20 @@
 use std::rc::Rc;
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
 
 use crate::error::{Result, RloxError};
 use crate::object::Object;
+use crate::symbol::Symbol;
 use crate::tokens::Token;
 
 #[derive(Debug)]
 pub(crate) struct Env {
     parent: Option<Rc<Env>>,
-    values: RefCell<HashMap<String, Object>>,
+    values: RefCell<HashMap<Symbol, Object>>,
+    // names defined with const, which can be neither assigned nor redefined
+    constants: RefCell<HashSet<Symbol>>,
 }
 
 impl Env {
@@ -16,6 +22,7 @@ impl Env {
         Rc::new(Self {
             parent: None,
             values: RefCell::new(HashMap::new()),
+            constants: RefCell::new(HashSet::new()),
         })
     }
 
@@ -23,13 +30,28 @@ impl Env {
         Rc::new(Self {
             parent: Some(Rc::clone(parent)),
             values: RefCell::new(HashMap::new()),
+            constants: RefCell::new(HashSet::new()),
         })
     }
 
     pub fn define(&self, id: &Token, val: Object) -> Result<()> {
-        let name = &id.lexeme;
+        let name = id.lexeme;
 
-        self.values.borrow_mut().insert(name.to_string(), val);
+        if self.constants.borrow().contains(&name) {
+            return Err(RloxError::Runtime(
+                id.line,
+                format!("Cannot redefine constant {}", name),
+                name.to_string(),
+            ));
+        }
+
+        self.values.borrow_mut().insert(name, val);
+        Ok(())
+    }
+
+    pub fn define_const(&self, id: &Token, val: Object) -> Result<()> {
+        self.define(id, val)?;
+        self.constants.borrow_mut().insert(id.lexeme);
         Ok(())
     }
 
@@ -47,16 +69,16 @@ impl Env {
         Err(RloxError::Runtime(
             id.line,
             format!("Ancestor is undefined at depth {}", dist),
-            id.lexeme.to_owned(),
+            id.lexeme.to_string(),
         ))
     }
 
     // recursive; if not in current scope then looks in parent scope
     pub fn assign(&self, id: &Token, val: Object) -> Result<Object> {
-        let name = &id.lexeme;
+        let name = id.lexeme;
         let mut values = self.values.borrow_mut();
 
-        if !values.contains_key(name) {
+        if !values.contains_key(&name) {
             if let Some(ref parent) = self.parent {
                 return parent.assign(id, val);
             }
@@ -68,7 +90,15 @@ impl Env {
             ));
         }
 
-        values.insert(name.to_string(), val.clone());
+        if self.constants.borrow().contains(&name) {
+            return Err(RloxError::Runtime(
+                id.line,
+                format!("Cannot assign to constant {}", name),
+                name.to_string(),
+            ));
+        }
+
+        values.insert(name, val.clone());
         Ok(val)
     }
 
@@ -91,16 +121,16 @@ impl Env {
         Err(RloxError::Runtime(
             id.line,
             format!("Ancestor is undefined at depth {}", dist),
-            id.lexeme.to_owned(),
+            id.lexeme.to_string(),
         ))
     }
 
     // recursive; if not in current scope then looks in parent scope
     pub fn get(&self, id: &Token) -> Result<Object> {
-        let name = &id.lexeme;
+        let name = id.lexeme;
         let values = self.values.borrow_mut();
 
-        if !values.contains_key(name) {
+        if !values.contains_key(&name) {
             if let Some(ref parent) = self.parent {
                 return parent.get(id);
             }
@@ -112,7 +142,7 @@ impl Env {
             ));
         }
 
-        Ok(values.get(name).cloned().unwrap())
+        Ok(values.get(&name).cloned().unwrap())
     }
 }
 
@@ -1,6 +1,6 @@
 use std::io;
 
-use crate::object::Object;
+use crate::{functions::TailCall, object::Object};
 
 #[derive(Debug)]
 pub(crate) enum RloxError {
@@ -10,7 +10,12 @@ pub(crate) enum RloxError {
     Parse(usize, String, String),
     Runtime(usize, String, String),
     Break(usize),
+    Continue(usize),
     Return(usize, Object),
+    // return f(x); unwinds to the calling function, which then runs f
+    TailCall(Box<TailCall>),
+    // a ?. link found nil; unwinds to the end of the call chain
+    ShortCircuit(usize),
 }
 
 impl From<io::Error> for RloxError {
@@ -37,9 +42,18 @@ impl std::fmt::Display for RloxError {
             RloxError::Break(ref line) => {
                 write!(f, "Error [line {}]: Unexpected break statement", line)
             }
+            RloxError::Continue(ref line) => {
+                write!(f, "Error [line {}]: Unexpected continue statement", line)
+            }
             RloxError::Return(ref line, _) => {
                 write!(f, "Error [line {}]: Unexpected Return statement", line)
             }
+            RloxError::TailCall(ref call) => {
+                write!(f, "Error [line {}]: Unexpected tail call", call.paren.line)
+            }
+            RloxError::ShortCircuit(ref line) => {
+                write!(f, "Error [line {}]: Unexpected optional chain", line)
+            }
         }
     }
 }
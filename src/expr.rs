@@ -1,17 +1,41 @@
-use crate::tokens::Token;
+use crate::{shape::InlineCache, tokens::Token};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum Expr {
     Identifier(Token),
     Literal(Token),
     Logical(Box<Expr>, Token, Box<Expr>),
+    // cond ? then : else
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    // lhs ?? rhs
+    Coalesce(Box<Expr>, Token, Box<Expr>),
     Grouping(Box<Expr>),
     Unary(Token, Box<Expr>),
     Binary(Box<Expr>, Token, Box<Expr>),
     Assignment(Token, Box<Expr>),
-    Call(Box<Expr>, Token, Vec<Expr>),
-    Get(Box<Expr>, Token),
-    Set(Box<Expr>, Token, Box<Expr>),
+    // [a, obj.b, list[0]] = value; each target is an Identifier, Get or Index
+    Destructure(Vec<Expr>, Token, Box<Expr>),
+    // target, binary operator, operand, whether the old value is returned;
+    // x += 1, obj.count -= 2, x++, --obj.count
+    Update(Box<Expr>, Token, Box<Expr>, bool),
+    // callee, paren, positional arguments and the named ones which follow them; f(1, b: 2)
+    Call(Box<Expr>, Token, Vec<Expr>, Vec<(Token, Expr)>),
+    Get(Box<Expr>, Token, InlineCache),
+    // obj?.prop; only valid inside an OptionalChain
+    OptionalGet(Box<Expr>, Token),
+    // a call chain containing at least one ?. which evaluates to nil if any of them does
+    OptionalChain(Box<Expr>),
+    Set(Box<Expr>, Token, Box<Expr>, InlineCache),
+    // [a, b, c]
+    List(Token, Vec<Expr>),
+    // {key: value, ...}
+    Map(Token, Vec<(Expr, Expr)>),
+    // start..end
+    Range(Box<Expr>, Token, Box<Expr>),
+    // list[index] or map[key]
+    Index(Box<Expr>, Token, Box<Expr>),
+    // list[index] = value or map[key] = value
+    SetIndex(Box<Expr>, Token, Box<Expr>, Box<Expr>),
     This(Token),
     Super(Token, Token),
 }
@@ -33,6 +57,14 @@ pub(crate) trait Visitor<T> {
         self.visit_expr(_expr)
     }
 
+    fn visit_conditional(&mut self, _expr: &Expr, _cond: &Expr, _then: &Expr, _else: &Expr) -> T {
+        self.visit_expr(_expr)
+    }
+
+    fn visit_coalesce(&mut self, _expr: &Expr, _lhs: &Expr, _op: &Token, _rhs: &Expr) -> T {
+        self.visit_expr(_expr)
+    }
+
     fn visit_grouping(&mut self, _expr: &Expr, _group: &Expr) -> T {
         self.visit_expr(_expr)
     }
@@ -49,15 +81,91 @@ pub(crate) trait Visitor<T> {
         self.visit_expr(_expr)
     }
 
-    fn visit_call(&mut self, _expr: &Expr, _callee: &Expr, _paren: &Token, _args: &[Expr]) -> T {
+    fn visit_destructure(
+        &mut self,
+        _expr: &Expr,
+        _targets: &[Expr],
+        _bracket: &Token,
+        _val: &Expr,
+    ) -> T {
+        self.visit_expr(_expr)
+    }
+
+    fn visit_update(
+        &mut self,
+        _expr: &Expr,
+        _target: &Expr,
+        _op: &Token,
+        _val: &Expr,
+        _postfix: bool,
+    ) -> T {
+        self.visit_expr(_expr)
+    }
+
+    fn visit_call(
+        &mut self,
+        _expr: &Expr,
+        _callee: &Expr,
+        _paren: &Token,
+        _args: &[Expr],
+        _named: &[(Token, Expr)],
+    ) -> T {
+        self.visit_expr(_expr)
+    }
+
+    fn visit_get(
+        &mut self,
+        _expr: &Expr,
+        _callee: &Expr,
+        _prop: &Token,
+        _cache: &InlineCache,
+    ) -> T {
+        self.visit_expr(_expr)
+    }
+
+    fn visit_optional_get(&mut self, _expr: &Expr, _callee: &Expr, _prop: &Token) -> T {
+        self.visit_expr(_expr)
+    }
+
+    fn visit_optional_chain(&mut self, _expr: &Expr, _chain: &Expr) -> T {
         self.visit_expr(_expr)
     }
 
-    fn visit_get(&mut self, _expr: &Expr, _callee: &Expr, _prop: &Token) -> T {
+    fn visit_set(
+        &mut self,
+        _expr: &Expr,
+        _settee: &Expr,
+        _prop: &Token,
+        _val: &Expr,
+        _cache: &InlineCache,
+    ) -> T {
         self.visit_expr(_expr)
     }
 
-    fn visit_set(&mut self, _expr: &Expr, _settee: &Expr, _prop: &Token, _val: &Expr) -> T {
+    fn visit_list(&mut self, _expr: &Expr, _bracket: &Token, _items: &[Expr]) -> T {
+        self.visit_expr(_expr)
+    }
+
+    fn visit_map(&mut self, _expr: &Expr, _brace: &Token, _entries: &[(Expr, Expr)]) -> T {
+        self.visit_expr(_expr)
+    }
+
+    fn visit_range(&mut self, _expr: &Expr, _start: &Expr, _op: &Token, _end: &Expr) -> T {
+        self.visit_expr(_expr)
+    }
+
+    fn visit_index(&mut self, _expr: &Expr, _list: &Expr, _bracket: &Token, _index: &Expr) -> T {
+        self.visit_expr(_expr)
+    }
+
+    fn visit_set_index(
+        &mut self,
+        _expr: &Expr,
+        _list: &Expr,
+        _bracket: &Token,
+        _index: &Expr,
+        _val: &Expr,
+    ) -> T {
         self.visit_expr(_expr)
     }
 
@@ -78,16 +186,35 @@ impl Expr {
             Identifier(ref id) => v.visit_identifier(self, id),
             Literal(ref lit) => v.visit_literal(self, lit),
             Logical(ref lhs, ref op, ref rhs) => v.visit_logical(self, lhs, op, rhs),
+            Conditional(ref cond, ref then, ref else_expr) => {
+                v.visit_conditional(self, cond, then, else_expr)
+            }
+            Coalesce(ref lhs, ref op, ref rhs) => v.visit_coalesce(self, lhs, op, rhs),
             Grouping(ref group) => v.visit_grouping(self, group),
             Unary(ref op, ref rhs) => v.visit_unary(self, op, rhs),
             Binary(ref lhs, ref op, ref rhs) => v.visit_binary(self, lhs, op, rhs),
             Assignment(ref id, ref val) => v.visit_assignment(self, id, val),
-            Call(ref callee, ref paren, ref args) => {
-                v.visit_call(self, callee.as_ref(), paren, args)
+            Destructure(ref targets, ref bracket, ref val) => {
+                v.visit_destructure(self, targets, bracket, val)
+            }
+            Update(ref target, ref op, ref val, postfix) => {
+                v.visit_update(self, target, op, val, *postfix)
+            }
+            Call(ref callee, ref paren, ref args, ref named) => {
+                v.visit_call(self, callee.as_ref(), paren, args, named)
             }
-            Get(ref callee, ref prop) => v.visit_get(self, callee.as_ref(), prop),
-            Set(ref settee, ref prop, ref val) => {
-                v.visit_set(self, settee.as_ref(), prop, val.as_ref())
+            Get(ref callee, ref prop, ref cache) => v.visit_get(self, callee.as_ref(), prop, cache),
+            OptionalGet(ref callee, ref prop) => v.visit_optional_get(self, callee.as_ref(), prop),
+            OptionalChain(ref chain) => v.visit_optional_chain(self, chain),
+            Set(ref settee, ref prop, ref val, ref cache) => {
+                v.visit_set(self, settee.as_ref(), prop, val.as_ref(), cache)
+            }
+            List(ref bracket, ref items) => v.visit_list(self, bracket, items),
+            Map(ref brace, ref entries) => v.visit_map(self, brace, entries),
+            Range(ref start, ref op, ref end) => v.visit_range(self, start, op, end),
+            Index(ref list, ref bracket, ref index) => v.visit_index(self, list, bracket, index),
+            SetIndex(ref list, ref bracket, ref index, ref val) => {
+                v.visit_set_index(self, list, bracket, index, val)
             }
             This(ref token) => v.visit_this(self, token),
             Super(ref token, ref method) => v.visit_super(self, token, method),
@@ -101,15 +228,34 @@ impl std::fmt::Display for Expr {
             Expr::Identifier(ref id) => write!(f, "{}", id),
             Expr::Literal(ref lit) => write!(f, "{}", lit),
             Expr::Logical(ref lhs, ref op, ref rhs) => write!(f, "({} {} {})", op, lhs, rhs),
+            Expr::Conditional(ref cond, ref then, ref else_expr) => {
+                write!(f, "(? {} {} {})", cond, then, else_expr)
+            }
+            Expr::Coalesce(ref lhs, ref op, ref rhs) => write!(f, "({} {} {})", op, lhs, rhs),
             Expr::Grouping(ref group) => write!(f, "(group {})", group),
             Expr::Unary(ref op, ref rhs) => write!(f, "({} {})", op, rhs),
             Expr::Binary(ref lhs, ref op, ref rhs) => write!(f, "({} {} {})", op, lhs, rhs),
             Expr::Assignment(ref id, ref val) => write!(f, "(= {} {})", id, val),
-            Expr::Call(ref callee, _, ref args) => write!(f, "{}({:?})", callee, args),
-            Expr::Get(ref callee, ref prop) => write!(f, "{}.{}", callee, prop),
-            Expr::Set(ref settee, ref prop, ref val) => {
+            Expr::Destructure(ref targets, _, ref val) => write!(f, "(= {:?} {})", targets, val),
+            Expr::Update(ref target, ref op, ref val, postfix) => {
+                write!(f, "({}= {} {} {})", op, target, val, postfix)
+            }
+            Expr::Call(ref callee, _, ref args, ref named) => {
+                write!(f, "{}({:?}, {:?})", callee, args, named)
+            }
+            Expr::Get(ref callee, ref prop, _) => write!(f, "{}.{}", callee, prop),
+            Expr::OptionalGet(ref callee, ref prop) => write!(f, "{}?.{}", callee, prop),
+            Expr::OptionalChain(ref chain) => write!(f, "{}", chain),
+            Expr::Set(ref settee, ref prop, ref val, _) => {
                 write!(f, "{}.{} = {}", settee.as_ref(), prop, val.as_ref())
             }
+            Expr::List(_, ref items) => write!(f, "{:?}", items),
+            Expr::Map(_, ref entries) => write!(f, "{:?}", entries),
+            Expr::Range(ref start, _, ref end) => write!(f, "{}..{}", start, end),
+            Expr::Index(ref list, _, ref index) => write!(f, "{}[{}]", list, index),
+            Expr::SetIndex(ref list, _, ref index, ref val) => {
+                write!(f, "{}[{}] = {}", list, index, val)
+            }
             Expr::This(_) => write!(f, "this"),
             Expr::Super(_, ref method) => write!(f, "super.{}", method.lexeme),
         }
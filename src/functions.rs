@@ -1,12 +1,13 @@
-use std::rc::Rc;
+use std::{cell::RefCell, rc::Rc};
 
 use crate::{
-    class::{LoxClass, LoxInstance, THIS},
+    class::{describe_unimplemented, LoxClass, LoxInstance, THIS},
     env::Env,
     error::{Result, RloxError},
+    generator::Generator,
     interpreter::Interpreter,
     object::Object,
-    stmt::Stmt,
+    stmt::{Body, Params},
     tokens::Token,
 };
 
@@ -22,10 +23,11 @@ pub(crate) enum FunctionType {
 pub(crate) enum Callable {
     Runtime(LoxFunction),
     Init(ClassInit),
+    Native(NativeFunction),
 }
 
 impl Callable {
-    pub fn new(env: &Rc<Env>, params: &[Token], body: &Stmt, init: bool) -> Self {
+    pub fn new(env: &Rc<Env>, params: &Rc<Params>, body: &Rc<Body>, init: bool) -> Self {
         Callable::Runtime(LoxFunction::new(env, params, body, init))
     }
 
@@ -33,17 +35,42 @@ impl Callable {
         Callable::Init(ClassInit(Rc::clone(class)))
     }
 
-    pub fn arity(&self) -> usize {
+    pub fn arity(&self) -> Arity {
         match self {
             Callable::Runtime(ref f) => f.arity(),
             Callable::Init(ref cls) => cls.arity(),
+            Callable::Native(ref f) => Arity::exactly(f.arity),
         }
     }
 
-    pub fn call(&self, interpreter: &Interpreter, args: &[Object]) -> Result<Object> {
+    pub fn call(
+        &self,
+        interpreter: &Interpreter,
+        paren: &Token,
+        args: &[Object],
+    ) -> Result<Object> {
+        self.call_named(interpreter, paren, args, &[])
+    }
+
+    // named arguments follow the positional ones and may not repeat them
+    pub fn call_named(
+        &self,
+        interpreter: &Interpreter,
+        paren: &Token,
+        args: &[Object],
+        named: &[(Token, Object)],
+    ) -> Result<Object> {
         match self {
-            Callable::Runtime(ref f) => f.call(interpreter, args),
-            Callable::Init(ref cls) => cls.call(interpreter, args),
+            Callable::Runtime(ref f) => f.call(interpreter, paren, args, named),
+            Callable::Init(ref cls) => cls.call(interpreter, paren, args, named),
+            Callable::Native(ref f) => match named.first() {
+                Some((name, _)) => Err(RloxError::Runtime(
+                    name.line,
+                    format!("{}() does not take named arguments", f.name),
+                    name.lexeme.to_string(),
+                )),
+                None => (f.func)(interpreter, paren, args),
+            },
         }
     }
 
@@ -55,40 +82,127 @@ impl Callable {
     }
 }
 
+// How many arguments a callable accepts; max is None with a rest parameter
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn exactly(n: usize) -> Self {
+        Self {
+            min: n,
+            max: Some(n),
+        }
+    }
+
+    pub fn accepts(&self, n: usize) -> bool {
+        n >= self.min && self.max.is_none_or(|max| n <= max)
+    }
+}
+
+impl std::fmt::Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct LoxFunction {
     closure: Rc<Env>,
-    params: Vec<Token>,
-    body: Box<Stmt>,
+    params: Rc<Params>,
+    body: Rc<Body>,
     init: bool,
 }
 
 impl LoxFunction {
-    pub fn new(scope: &Rc<Env>, params: &[Token], body: &Stmt, init: bool) -> Self {
+    pub fn new(scope: &Rc<Env>, params: &Rc<Params>, body: &Rc<Body>, init: bool) -> Self {
         Self {
             closure: Rc::clone(scope),
-            params: params.to_vec(),
-            body: Box::new(body.clone()),
+            params: Rc::clone(params),
+            body: Rc::clone(body),
             init,
         }
     }
 
-    pub fn arity(&self) -> usize {
-        self.params.len()
+    pub fn arity(&self) -> Arity {
+        Arity {
+            min: self.params.required(),
+            max: match self.params.rest {
+                Some(_) => None,
+                None => Some(self.params.list.len()),
+            },
+        }
+    }
+
+    // Calls in tail position come back here as a TailCall error, so a chain
+    // of them runs in this loop instead of growing the Rust stack
+    pub fn call(
+        &self,
+        interpreter: &Interpreter,
+        paren: &Token,
+        args: &[Object],
+        named: &[(Token, Object)],
+    ) -> Result<Object> {
+        let ret = self.call_once(&self.closure, interpreter, paren, args, named);
+        LoxFunction::trampoline(interpreter, ret)
+    }
+
+    // Calls this method of inst as bind(inst).call(..) would, without
+    // building the bound function
+    pub fn call_method(
+        &self,
+        inst: &LoxInstance,
+        interpreter: &Interpreter,
+        paren: &Token,
+        args: &[Object],
+        named: &[(Token, Object)],
+    ) -> Result<Object> {
+        let ret = self.call_once(&self.this_env(inst), interpreter, paren, args, named);
+        LoxFunction::trampoline(interpreter, ret)
+    }
+
+    fn trampoline(interpreter: &Interpreter, mut ret: Result<Object>) -> Result<Object> {
+        while let Err(RloxError::TailCall(call)) = ret {
+            let TailCall {
+                func,
+                paren,
+                args,
+                named,
+            } = *call;
+            ret = func.call_once(&func.closure, interpreter, &paren, &args, &named);
+        }
+
+        ret
     }
 
-    pub fn call(&self, interpreter: &Interpreter, args: &[Object]) -> Result<Object> {
+    // runs the body in a new scope inside closure
+    fn call_once(
+        &self,
+        closure: &Rc<Env>,
+        interpreter: &Interpreter,
+        paren: &Token,
+        args: &[Object],
+        named: &[(Token, Object)],
+    ) -> Result<Object> {
         use crate::tokens::Literal::Nil;
 
-        let env = Env::from(&self.closure);
+        let env = Env::from(closure);
+        self.bind_args(interpreter, &env, paren, args, named)?;
 
-        for (param, arg) in self.params.iter().zip(args.iter()) {
-            env.define(param, arg.clone())?;
+        if self.body.generator {
+            let gen = Generator::new(Rc::clone(&self.body), &env);
+            return Ok(Object::Generator(Rc::new(RefCell::new(gen))));
         }
 
-        match self.body.accept(&mut interpreter.with_env(env)) {
+        match self.body.stmt.accept(&mut interpreter.with_env(env)) {
             Ok(()) | Err(RloxError::Return(_, _)) if self.init => {
-                self.closure.get_at(&THIS, Some(0))
+                THIS.with(|this| closure.get_at(this, Some(0)))
             }
             Ok(()) => Ok(Object::Literal(Nil)),
             Err(RloxError::Return(_, ret)) => Ok(ret),
@@ -96,30 +210,162 @@ impl LoxFunction {
         }
     }
 
+    // Defines the parameters in env. Defaults are evaluated there too,
+    // so they can refer to earlier parameters.
+    fn bind_args(
+        &self,
+        interpreter: &Interpreter,
+        env: &Rc<Env>,
+        paren: &Token,
+        args: &[Object],
+        named: &[(Token, Object)],
+    ) -> Result<()> {
+        if let Some((name, _)) = named
+            .iter()
+            .find(|(name, _)| !self.params.names().any(|p| p.lexeme == name.lexeme))
+        {
+            return Err(RloxError::Runtime(
+                name.line,
+                "No parameter with this name".to_string(),
+                name.lexeme.to_string(),
+            ));
+        }
+
+        for (i, (param, default)) in self.params.list.iter().enumerate() {
+            let by_name = named.iter().find(|(name, _)| name.lexeme == param.lexeme);
+
+            let val = match (args.get(i), by_name, default) {
+                (Some(_), Some((name, _)), _) => {
+                    return Err(RloxError::Runtime(
+                        name.line,
+                        "Argument given both by position and by name".to_string(),
+                        name.lexeme.to_string(),
+                    ))
+                }
+                (Some(arg), None, _) => arg.clone(),
+                (None, Some((_, arg)), _) => arg.clone(),
+                (None, None, Some(default)) => {
+                    default.accept(&mut interpreter.with_env(Rc::clone(env)))?
+                }
+                (None, None, None) => {
+                    return Err(RloxError::Runtime(
+                        paren.line,
+                        "Missing argument for parameter".to_string(),
+                        param.lexeme.to_string(),
+                    ))
+                }
+            };
+
+            env.define(param, val)?;
+        }
+
+        if let Some(ref rest) = self.params.rest {
+            let extra = args.iter().skip(self.params.list.len()).cloned().collect();
+            env.define(rest, Object::list(extra))?;
+        }
+
+        Ok(())
+    }
+
     pub fn bind(&self, inst: &LoxInstance) -> Self {
+        Self {
+            closure: self.this_env(inst),
+            params: Rc::clone(&self.params),
+            body: Rc::clone(&self.body),
+            init: self.init,
+        }
+    }
+
+    // the scope a method runs in, with this defined
+    fn this_env(&self, inst: &LoxInstance) -> Rc<Env> {
         let env = Env::from(&self.closure);
-        env.define(&THIS, Object::Instance(inst.clone()))
+        THIS.with(|this| env.define(this, Object::Instance(inst.clone())))
             .expect("Failed to define 'this'");
-
-        Self::new(&env, &self.params, &self.body, self.init)
+        env
     }
 }
 
+// A call made by 'return f(x);', with its arguments already evaluated
+#[derive(Debug)]
+pub(crate) struct TailCall {
+    pub func: LoxFunction,
+    pub paren: Token,
+    pub args: Vec<Object>,
+    pub named: Vec<(Token, Object)>,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct ClassInit(Rc<LoxClass>);
 
 impl ClassInit {
-    pub fn arity(&self) -> usize {
-        self.0.find_method("init").map_or(0, |init| init.arity())
+    pub fn arity(&self) -> Arity {
+        self.0
+            .find_method("init".into())
+            .map_or(Arity::exactly(0), |init| init.arity())
     }
 
-    pub fn call(&self, interpreter: &Interpreter, args: &[Object]) -> Result<Object> {
+    pub fn call(
+        &self,
+        interpreter: &Interpreter,
+        paren: &Token,
+        args: &[Object],
+        named: &[(Token, Object)],
+    ) -> Result<Object> {
+        let missing = self.0.unimplemented();
+        if !missing.is_empty() {
+            return Err(RloxError::Runtime(
+                paren.line,
+                format!(
+                    "Cannot instantiate abstract class {}; missing {}",
+                    self.0,
+                    describe_unimplemented(&missing)
+                ),
+                format!("{}", self.0),
+            ));
+        }
+
         let inst = LoxInstance::new(&self.0);
+        self.0.init_fields(interpreter, &inst)?;
 
-        if let Some(init) = self.0.find_method("init") {
-            init.bind(&inst).call(interpreter, args)?;
+        if let Some(init) = self.0.find_method("init".into()) {
+            init.bind(&inst)
+                .call_named(interpreter, paren, args, named)?;
         }
 
         Ok(Object::Instance(inst))
     }
 }
+
+// receives the closing paren of the call for error reporting
+type NativeFn = dyn Fn(&Interpreter, &Token, &[Object]) -> Result<Object>;
+
+// A function implemented in Rust
+#[derive(Clone)]
+pub(crate) struct NativeFunction {
+    name: &'static str,
+    arity: usize,
+    func: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &'static str, arity: usize, func: F) -> Self
+    where
+        F: Fn(&Interpreter, &Token, &[Object]) -> Result<Object> + 'static,
+    {
+        Self {
+            name,
+            arity,
+            func: Rc::new(func),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native {}>", self.name)
+    }
+}
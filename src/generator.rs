@@ -0,0 +1,261 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    env::Env,
+    error::{Result, RloxError},
+    functions::{Callable, NativeFunction},
+    interpreter::Interpreter,
+    iter::LoxIterator,
+    object::Object,
+    stmt::{Body, Stmt},
+    tokens::{Literal, Token},
+};
+
+// A suspended call of a function containing 'yield'.
+//
+// The body cannot be run by the interpreter directly since that would keep
+// its position on the Rust stack. Instead every compound statement that
+// contains a 'yield' gets a Frame recording where it is, and everything
+// else is handed to the interpreter as usual. Resuming picks up from the
+// innermost frame.
+//
+// A 'return' ends the generator; any value it returns is discarded.
+#[derive(Debug)]
+pub(crate) struct Generator {
+    body: Rc<Body>,
+    frames: Vec<Frame>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    // where this frame's statement is, relative to the statement of the frame below
+    path: Vec<usize>,
+    env: Rc<Env>,
+    state: State,
+}
+
+#[derive(Debug)]
+enum State {
+    // index of the next statement of a block
+    Block(usize),
+    // whether the body has run, so the increment is due
+    While(bool),
+    ForIn(LoxIterator),
+}
+
+enum Step<'a> {
+    Enter(&'a Stmt, Vec<usize>, Rc<Env>),
+    Pop,
+}
+
+impl Generator {
+    // env holds the arguments of the call
+    pub(crate) fn new(body: Rc<Body>, env: &Rc<Env>) -> Self {
+        Self {
+            body,
+            frames: vec![Frame {
+                path: Vec::new(),
+                env: Env::from(env),
+                state: State::Block(0),
+            }],
+        }
+    }
+
+    // Resumes gen, failing if it is the generator currently running
+    pub(crate) fn next(
+        gen: &Rc<RefCell<Self>>,
+        interpreter: &Interpreter,
+        token: &Token,
+    ) -> Result<Option<Object>> {
+        match gen.try_borrow_mut() {
+            Ok(mut gen) => gen.resume(interpreter),
+            Err(_) => Err(RloxError::Runtime(
+                token.line,
+                "Generator is already running".to_string(),
+                token.lexeme.to_string(),
+            )),
+        }
+    }
+
+    // The next() method of a generator; returns nil once it is done
+    pub(crate) fn next_method(gen: &Rc<RefCell<Self>>) -> Object {
+        let gen = Rc::clone(gen);
+
+        let next = NativeFunction::new("next", 0, move |interpreter, paren, _| {
+            let item = Generator::next(&gen, interpreter, paren)?;
+            Ok(item.unwrap_or(Object::Literal(Literal::Nil)))
+        });
+
+        Object::func(Callable::Native(next))
+    }
+
+    // Runs until the next 'yield'. Returns None once the body has finished.
+    pub(crate) fn resume(&mut self, interpreter: &Interpreter) -> Result<Option<Object>> {
+        let body = Rc::clone(&self.body);
+
+        while !self.frames.is_empty() {
+            match self.step(interpreter, &body) {
+                Ok(Some(val)) => return Ok(Some(val)),
+                Ok(None) => (),
+                Err(RloxError::Break(_)) => self.unwind_loop(true),
+                Err(RloxError::Continue(_)) => self.unwind_loop(false),
+                Err(RloxError::Return(_, _)) => self.frames.clear(),
+                Err(e) => {
+                    self.frames.clear();
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Advances the innermost frame by one statement
+    fn step(&mut self, interpreter: &Interpreter, body: &Body) -> Result<Option<Object>> {
+        let top = self.frames.len() - 1;
+        let stmt = self.stmt_at(&body.stmt, top);
+        let frame = &mut self.frames[top];
+        let env = Rc::clone(&frame.env);
+
+        let step = match (stmt, &mut frame.state) {
+            (Stmt::Block(ref stmts), State::Block(ref mut next)) if *next < stmts.len() => {
+                *next += 1;
+                Step::Enter(&stmts[*next - 1], vec![*next - 1], env)
+            }
+            (Stmt::While(ref cond, ref body, ref inc), State::While(ref mut ran)) => {
+                let mut scope = interpreter.with_env(Rc::clone(&env));
+                if let (true, Some(inc)) = (*ran, inc) {
+                    inc.accept(&mut scope)?;
+                }
+                *ran = true;
+
+                if cond.accept(&mut scope)?.is_truthy() {
+                    Step::Enter(body, vec![0], env)
+                } else {
+                    Step::Pop
+                }
+            }
+            (Stmt::ForIn(ref id, _, ref body), State::ForIn(ref mut iter)) => {
+                match iter.next(interpreter, id)? {
+                    Some(item) => {
+                        let scope = Env::from(&env);
+                        scope.define(id, item)?;
+                        Step::Enter(body, vec![0], scope)
+                    }
+                    None => Step::Pop,
+                }
+            }
+            _ => Step::Pop,
+        };
+
+        match step {
+            Step::Enter(stmt, path, env) => self.enter(interpreter, stmt, path, env),
+            Step::Pop => {
+                self.frames.pop();
+                Ok(None)
+            }
+        }
+    }
+
+    // Starts executing stmt, pushing a frame if it may suspend
+    fn enter(
+        &mut self,
+        interpreter: &Interpreter,
+        stmt: &Stmt,
+        mut path: Vec<usize>,
+        env: Rc<Env>,
+    ) -> Result<Option<Object>> {
+        let mut scope = interpreter.with_env(Rc::clone(&env));
+
+        if !self.body.yields(stmt) {
+            stmt.accept(&mut scope)?;
+            return Ok(None);
+        }
+
+        let state = match stmt {
+            Stmt::Yield(_, ref val) => {
+                let val = match val {
+                    Some(val) => val.accept(&mut scope)?,
+                    None => Object::Literal(Literal::Nil),
+                };
+                return Ok(Some(val));
+            }
+            Stmt::Block(_) => {
+                self.frames.push(Frame {
+                    path,
+                    env: Env::from(&env),
+                    state: State::Block(0),
+                });
+                return Ok(None);
+            }
+            Stmt::If(ref cond, ref then, ref else_stmt) => {
+                if cond.accept(&mut scope)?.is_truthy() {
+                    path.push(0);
+                    return self.enter(interpreter, then, path, env);
+                }
+
+                return match else_stmt {
+                    Some(else_stmt) => {
+                        path.push(1);
+                        self.enter(interpreter, else_stmt, path, env)
+                    }
+                    None => Ok(None),
+                };
+            }
+            Stmt::Match(ref keyword, ref subject, ref arms) => {
+                let val = subject.accept(&mut scope)?;
+
+                for (i, (pattern, body)) in arms.iter().enumerate() {
+                    let mut bindings = Vec::new();
+                    if !scope.match_pattern(pattern, &val, &mut bindings)? {
+                        continue;
+                    }
+
+                    let arm = Env::from(&env);
+                    for (id, val) in bindings {
+                        arm.define(&id, val)?;
+                    }
+
+                    path.push(i);
+                    return self.enter(interpreter, body, path, arm);
+                }
+
+                return Err(RloxError::Runtime(
+                    keyword.line,
+                    "No match arm matched value".to_string(),
+                    format!("{}", val),
+                ));
+            }
+            Stmt::While(_, _, _) => State::While(false),
+            Stmt::ForIn(ref id, ref iterable, _) => {
+                let iterable = iterable.accept(&mut scope)?;
+                State::ForIn(LoxIterator::new(interpreter, iterable, id)?)
+            }
+            _ => unreachable!("only compound statements can contain yield"),
+        };
+
+        self.frames.push(Frame { path, env, state });
+        Ok(None)
+    }
+
+    // Pops frames up to the innermost loop, which is also popped on a break
+    fn unwind_loop(&mut self, is_break: bool) {
+        while let Some(frame) = self.frames.last() {
+            if let State::While(_) | State::ForIn(_) = frame.state {
+                if is_break {
+                    self.frames.pop();
+                }
+                return;
+            }
+
+            self.frames.pop();
+        }
+    }
+
+    fn stmt_at<'a>(&self, body: &'a Stmt, frame: usize) -> &'a Stmt {
+        self.frames[..=frame]
+            .iter()
+            .flat_map(|f| f.path.iter())
+            .fold(body, |stmt, &i| stmt.child(i))
+    }
+}
@@ -1,21 +1,38 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::{
-    class::{LoxClass, SUPER, THIS},
+    class::{self, FieldDecls, LoxClass, LoxInstance, LoxTrait, Property, OWNER, SUPER, THIS},
+    enums::LoxEnum,
     env::Env,
     error::{Result, RloxError},
     expr::{Expr, Visitor as ExprVisitor},
-    functions::Callable,
+    functions::{Arity, Callable, TailCall},
+    generator::Generator,
+    iter::LoxIterator,
+    map::LoxMap,
+    natives,
     object::Object,
-    stmt::{Stmt, Visitor as StmtVisitor},
-    tokens::{Literal, Token},
+    pattern::Pattern,
+    shape::InlineCache,
+    stmt::{Body, Destructure, Params, Stmt, Visitor as StmtVisitor},
+    symbol::Symbol,
+    tokens::{Literal, Token, TokenType},
 };
 use Object::Literal as ObjLit;
 
+// positional and named arguments, evaluated
+type Args = (Vec<Object>, Vec<(Token, Object)>);
+
 pub(crate) struct Interpreter {
     pub(crate) repl: bool,
     pub(crate) env: Rc<Env>,
     locals: Rc<HashMap<Expr, usize>>,
+    // calls the resolver found directly after a 'return'
+    tail_calls: Rc<HashSet<Expr>>,
 }
 
 impl ExprVisitor<Result<Object>> for Interpreter {
@@ -46,6 +63,34 @@ impl ExprVisitor<Result<Object>> for Interpreter {
         }
     }
 
+    fn visit_conditional(
+        &mut self,
+        _expr: &Expr,
+        cond: &Expr,
+        then: &Expr,
+        else_expr: &Expr,
+    ) -> Result<Object> {
+        if cond.accept(self)?.is_truthy() {
+            then.accept(self)
+        } else {
+            else_expr.accept(self)
+        }
+    }
+
+    // unlike 'or' only nil falls back to the right hand side
+    fn visit_coalesce(
+        &mut self,
+        _expr: &Expr,
+        lhs: &Expr,
+        _op: &Token,
+        rhs: &Expr,
+    ) -> Result<Object> {
+        match lhs.accept(self)? {
+            ObjLit(Literal::Nil) => rhs.accept(self),
+            lhs => Ok(lhs),
+        }
+    }
+
     fn visit_grouping(&mut self, _expr: &Expr, group: &Expr) -> Result<Object> {
         group.accept(self)
     }
@@ -56,118 +101,134 @@ impl ExprVisitor<Result<Object>> for Interpreter {
 
         match op.token_type {
             Minus => match rhs {
+                ObjLit(Literal::Integer(i)) => Ok(ObjLit(
+                    i.checked_neg()
+                        .map_or(Literal::Number(-(i as f64)), Literal::Integer),
+                )),
                 ObjLit(Literal::Number(n)) => Ok(ObjLit(Literal::Number(-n))),
                 _ => self.err_near("Cannot negate non-numeric value", op, format!("{:?}", rhs)),
             },
+            Tilde => match rhs {
+                ObjLit(ref lit) if lit.as_i64().is_some() => {
+                    Ok(ObjLit(Literal::Integer(!lit.as_i64().unwrap())))
+                }
+                _ => self.err_near(
+                    "Bitwise operand must be an integer",
+                    op,
+                    format!("{:?}", rhs),
+                ),
+            },
             Bang => Ok(ObjLit(Literal::Boolean(!rhs.is_truthy()))),
             _ => self.err_op("Invalid unary operator", op),
         }
     }
 
     fn visit_binary(&mut self, _expr: &Expr, lhs: &Expr, op: &Token, rhs: &Expr) -> Result<Object> {
-        use std::cmp::Ordering;
-
-        use crate::tokens::TokenType::*;
-
         let lhs = lhs.accept(self)?;
         let rhs = rhs.accept(self)?;
 
-        let result = match op.token_type {
-            Plus => match (lhs, rhs) {
-                (ObjLit(Literal::Number(left_num)), ObjLit(Literal::Number(right_num))) => {
-                    Literal::Number(left_num + right_num)
-                }
-                (ObjLit(Literal::String(ref ls)), ObjLit(ref r)) => {
-                    Literal::String(format!("{}{}", ls, r))
-                }
-                (ObjLit(ref l), ObjLit(Literal::String(ref rs))) => {
-                    Literal::String(format!("{}{}", l, rs))
-                }
-
-                (_l, _r) => {
-                    return self.err_near(
-                        "Cannot add mixed types",
-                        op,
-                        format!("{:?} + {:?}", _l, _r),
-                    )
-                }
-            },
-            Minus => match (lhs, rhs) {
-                (ObjLit(Literal::Number(left_num)), ObjLit(Literal::Number(right_num))) => {
-                    Literal::Number(left_num - right_num)
-                }
-
-                (_l, _r) => {
-                    return self.err_near(
-                        "Cannot subtract non-numeric operands",
-                        op,
-                        format!("{:?} - {:?}", _l, _r),
-                    )
-                }
-            },
-
-            Star => match (lhs, rhs) {
-                (ObjLit(Literal::Number(left_num)), ObjLit(Literal::Number(right_num))) => {
-                    Literal::Number(left_num * right_num)
-                }
+        self.binary_op(lhs, op, rhs)
+    }
 
-                (_l, _r) => {
-                    return self.err_near(
-                        "Cannot multiply non-numeric operands",
-                        op,
-                        format!("{:?} * {:?}", _l, _r),
-                    )
-                }
-            },
+    fn visit_assignment(&mut self, expr: &Expr, id: &Token, val: &Expr) -> Result<Object> {
+        let v = val.accept(self)?;
+        self.env.assign_at(id, v, self.locals.get(expr).copied())
+    }
 
-            Slash => match (lhs, rhs) {
-                (ObjLit(Literal::Number(left_num)), ObjLit(Literal::Number(right_num)))
-                    if right_num == 0.0 =>
-                {
-                    return self.err_near(
-                        "Divide by zero!! Fucker!",
-                        op,
-                        format!("{:?} / {:?}", left_num, right_num),
-                    )
+    fn visit_destructure(
+        &mut self,
+        _expr: &Expr,
+        targets: &[Expr],
+        bracket: &Token,
+        val: &Expr,
+    ) -> Result<Object> {
+        let val = val.accept(self)?;
+        let (items, _) = Interpreter::unpack(&val, targets.len(), false, bracket)?;
+
+        // every item is read before any target is assigned, so [a, b] = [b, a] swaps
+        for (target, item) in targets.iter().zip(items) {
+            match target {
+                Expr::Identifier(ref id) => {
+                    self.env
+                        .assign_at(id, item, self.locals.get(target).copied())?;
                 }
-                (ObjLit(Literal::Number(left_num)), ObjLit(Literal::Number(right_num))) => {
-                    Literal::Number(left_num / right_num)
+                Expr::Get(ref settee, ref prop, ref cache) => match settee.accept(self)? {
+                    Object::Instance(ref inst) => {
+                        self.set_prop(inst, prop, item, Some(cache))?;
+                    }
+                    _ => {
+                        return Err(RloxError::Runtime(
+                            prop.line,
+                            "Only instances have fields".to_string(),
+                            prop.lexeme.to_string(),
+                        ))
+                    }
+                },
+                Expr::Index(ref list, ref bracket, ref index) => {
+                    let list = list.accept(self)?;
+                    let index = index.accept(self)?;
+                    self.index_set(&list, bracket, index, item)?;
                 }
+                _ => unreachable!("the parser only allows assignable targets"),
+            }
+        }
 
-                (_l, _r) => {
-                    return self.err_near(
-                        "Cannot divide non-numerics",
-                        op,
-                        format!("{:?} / {:?}", _l, _r),
-                    )
-                }
-            },
+        Ok(val)
+    }
 
-            Greater | GreaterEqual | Less | LessEqual => match lhs.partial_cmp(&rhs) {
-                Some(Ordering::Less) => Literal::Boolean(op.in_types(&[Less, LessEqual])),
-                Some(Ordering::Equal) => Literal::Boolean(op.in_types(&[LessEqual, GreaterEqual])),
-                Some(Ordering::Greater) => Literal::Boolean(op.in_types(&[Greater, GreaterEqual])),
-                None => {
-                    return self.err_near(
-                        "Cannot compare types",
-                        op,
-                        format!("{:?} (compare) {:?}", lhs, rhs),
-                    )
-                }
-            },
+    fn visit_update(
+        &mut self,
+        _expr: &Expr,
+        target: &Expr,
+        op: &Token,
+        val: &Expr,
+        postfix: bool,
+    ) -> Result<Object> {
+        // the receiver of a field is only evaluated once
+        let (old, new) = match target {
+            Expr::Identifier(ref id) => {
+                let old = self.lookup_var(id, target)?;
+                let val = val.accept(self)?;
+                let new = self.binary_op(old.clone(), op, val)?;
+
+                self.env
+                    .assign_at(id, new.clone(), self.locals.get(target).copied())?;
+                (old, new)
+            }
+            Expr::Get(ref settee, ref prop, ref cache) => {
+                let inst = match settee.accept(self)? {
+                    Object::Instance(inst) => inst,
+                    _ => {
+                        return Err(RloxError::Runtime(
+                            prop.line,
+                            "Only instances have fields".to_string(),
+                            prop.lexeme.to_string(),
+                        ))
+                    }
+                };
+
+                let old = self.get_prop(&inst, prop, Some(cache))?;
+                let val = val.accept(self)?;
+                let new = self.binary_op(old.clone(), op, val)?;
+
+                self.set_prop(&inst, prop, new.clone(), Some(cache))?;
+                (old, new)
+            }
+            Expr::Index(ref list, ref bracket, ref index) => {
+                let list = list.accept(self)?;
+                let index = index.accept(self)?;
 
-            EqualEqual => Literal::Boolean(lhs.eq(&rhs)),
-            BangEqual => Literal::Boolean(lhs.ne(&rhs)),
+                let old = self.index_get(&list, bracket, index.clone())?;
+                let val = val.accept(self)?;
+                let new = self.binary_op(old.clone(), op, val)?;
 
-            _ => return self.err_op("Invalid binary operator", op),
+                self.index_set(&list, bracket, index, new.clone())?;
+                (old, new)
+            }
+            _ => unreachable!(),
         };
 
-        Ok(ObjLit(result))
-    }
-
-    fn visit_assignment(&mut self, _expr: &Expr, id: &Token, val: &Expr) -> Result<Object> {
-        let v = val.accept(self)?;
-        self.env.assign_at(id, v, self.locals.get(val).copied())
+        Ok(if postfix { old } else { new })
     }
 
     fn visit_identifier(&mut self, expr: &Expr, id: &Token) -> Result<Object> {
@@ -180,28 +241,62 @@ impl ExprVisitor<Result<Object>> for Interpreter {
         callee: &Expr,
         paren: &Token,
         args: &[Expr],
+        named: &[(Token, Expr)],
+    ) -> Result<Object> {
+        // obj.method(..) runs the method with this defined, without binding it first
+        if let Expr::Get(ref obj, ref prop, ref cache) = *callee {
+            if prop.token_type != TokenType::Private {
+                let callee = match obj.accept(self)? {
+                    Object::Instance(ref inst) => match inst.property(prop, Some(cache))? {
+                        Property::Method(Callable::Runtime(ref method)) => {
+                            let (args, named) =
+                                self.eval_args(method.arity(), paren, args, named)?;
+                            return method.call_method(inst, self, paren, &args, &named);
+                        }
+                        Property::Method(method) => Object::func(method.bind(inst)),
+                        Property::Value(val) => val,
+                    },
+                    obj => self.get_property(obj, prop)?,
+                };
+
+                return self.call_object(callee, paren, args, named);
+            }
+        }
+
+        let callee = callee.accept(self)?;
+        self.call_object(callee, paren, args, named)
+    }
+
+    fn visit_get(
+        &mut self,
+        _expr: &Expr,
+        callee: &Expr,
+        prop: &Token,
+        cache: &InlineCache,
     ) -> Result<Object> {
         match callee.accept(self)? {
-            Object::Func(ref f) => self.call_dispatch(f, paren, args),
-            Object::Class(ref cls) => self.call_dispatch(&Callable::init(cls), paren, args),
-            x => self.err_near(
-                "Can only call functions and classes",
-                paren,
-                format!("{:?}", x),
-            ),
+            Object::Instance(ref inst) => self.get_prop(inst, prop, Some(cache)),
+            obj => self.get_property(obj, prop),
         }
     }
 
-    fn visit_get(&mut self, _expr: &Expr, callee: &Expr, prop: &Token) -> Result<Object> {
-        if let Object::Instance(ref inst) = callee.accept(self)? {
-            return inst.get(prop);
+    fn visit_optional_get(&mut self, _expr: &Expr, callee: &Expr, prop: &Token) -> Result<Object> {
+        match callee.accept(self)? {
+            Object::Instance(ref inst) => self.get_prop(inst, prop, None),
+            ObjLit(Literal::Nil) => Err(RloxError::ShortCircuit(prop.line)),
+            _ => Err(RloxError::Runtime(
+                prop.line,
+                "Only instances have properties".to_string(),
+                prop.lexeme.to_string(),
+            )),
         }
+    }
 
-        Err(RloxError::Runtime(
-            prop.line,
-            "Only instances have properties".to_string(),
-            prop.lexeme.to_owned(),
-        ))
+    fn visit_optional_chain(&mut self, _expr: &Expr, chain: &Expr) -> Result<Object> {
+        match chain.accept(self) {
+            Err(RloxError::ShortCircuit(_)) => Ok(ObjLit(Literal::Nil)),
+            res => res,
+        }
     }
 
     fn visit_set(
@@ -210,18 +305,93 @@ impl ExprVisitor<Result<Object>> for Interpreter {
         settee: &Expr,
         prop: &Token,
         val: &Expr,
+        cache: &InlineCache,
     ) -> Result<Object> {
         if let Object::Instance(ref inst) = settee.accept(self)? {
-            inst.set(prop, val.accept(self)?)
+            let val = val.accept(self)?;
+            self.set_prop(inst, prop, val, Some(cache))
         } else {
             Err(RloxError::Runtime(
                 prop.line,
                 "Only instances have fields".to_string(),
-                prop.lexeme.to_owned(),
+                prop.lexeme.to_string(),
             ))
         }
     }
 
+    fn visit_list(&mut self, _expr: &Expr, _bracket: &Token, items: &[Expr]) -> Result<Object> {
+        let mut list = Vec::with_capacity(items.len());
+        for item in items {
+            list.push(item.accept(self)?);
+        }
+
+        Ok(Object::list(list))
+    }
+
+    fn visit_map(
+        &mut self,
+        _expr: &Expr,
+        brace: &Token,
+        entries: &[(Expr, Expr)],
+    ) -> Result<Object> {
+        let mut map = LoxMap::default();
+        for (key, val) in entries {
+            let key = key.accept(self)?;
+            let key = self.map_key(brace, key)?;
+            map.insert(key, val.accept(self)?);
+        }
+
+        Ok(Object::Map(Rc::new(RefCell::new(map))))
+    }
+
+    fn visit_range(
+        &mut self,
+        _expr: &Expr,
+        start: &Expr,
+        op: &Token,
+        end: &Expr,
+    ) -> Result<Object> {
+        match (start.accept(self)?, end.accept(self)?) {
+            (ObjLit(Literal::Integer(start)), ObjLit(Literal::Integer(end))) => {
+                Ok(Object::Range(Rc::new(start..end)))
+            }
+            (start, end) => self.err_near(
+                "Range bounds must be integers",
+                op,
+                format!("{}..{}", start, end),
+            ),
+        }
+    }
+
+    fn visit_index(
+        &mut self,
+        _expr: &Expr,
+        list: &Expr,
+        bracket: &Token,
+        index: &Expr,
+    ) -> Result<Object> {
+        let list = list.accept(self)?;
+        let index = index.accept(self)?;
+
+        self.index_get(&list, bracket, index)
+    }
+
+    fn visit_set_index(
+        &mut self,
+        _expr: &Expr,
+        list: &Expr,
+        bracket: &Token,
+        index: &Expr,
+        val: &Expr,
+    ) -> Result<Object> {
+        let list = list.accept(self)?;
+        let index = index.accept(self)?;
+        let val = val.accept(self)?;
+
+        self.index_set(&list, bracket, index, val.clone())?;
+        Ok(val)
+    }
+
     fn visit_this(&mut self, expr: &Expr, token: &Token) -> Result<Object> {
         self.lookup_var(token, expr)
     }
@@ -238,29 +408,29 @@ impl ExprVisitor<Result<Object>> for Interpreter {
                 return Err(RloxError::Runtime(
                     keyword.line,
                     "Unexpected 'super'".to_string(),
-                    keyword.lexeme.to_owned(),
+                    keyword.lexeme.to_string(),
                 ));
             }
         };
 
         // The current object will always be the child
-        let inst = match self.env.get_at(&THIS, Some(dist - 1))? {
+        let inst = match THIS.with(|this| self.env.get_at(this, Some(dist - 1)))? {
             Object::Instance(ref i) => i.clone(),
             _ => {
                 return Err(RloxError::Runtime(
                     keyword.line,
                     "Unexpected 'this'".to_string(),
-                    keyword.lexeme.to_owned(),
+                    keyword.lexeme.to_string(),
                 ));
             }
         };
 
-        match parent.find_method(&method.lexeme) {
-            Some(m) => Ok(Object::Func(m.bind(&inst))),
+        match parent.find_method(method.lexeme) {
+            Some(m) => Ok(Object::func(m.bind(&inst))),
             None => Err(RloxError::Runtime(
                 method.line,
                 "Undefined property {}".to_string(),
-                method.lexeme.to_owned(),
+                method.lexeme.to_string(),
             )),
         }
     }
@@ -293,6 +463,55 @@ impl StmtVisitor<Result<()>> for Interpreter {
         self.env.define(id, val)
     }
 
+    fn visit_destructure(&mut self, _stmt: &Stmt, target: &Destructure, init: &Expr) -> Result<()> {
+        let val = init.accept(self)?;
+
+        match target {
+            Destructure::List(ref bracket, ref names, ref rest) => {
+                let (items, extra) =
+                    Interpreter::unpack(&val, names.len(), rest.is_some(), bracket)?;
+
+                for (id, item) in names.iter().zip(items) {
+                    self.env.define(id, item)?;
+                }
+
+                if let Some(rest) = rest {
+                    self.env.define(rest, Object::list(extra))?;
+                }
+            }
+            Destructure::Fields(ref brace, ref names) => {
+                for id in names {
+                    let field = match val {
+                        Object::Instance(ref inst) => inst.get(id).unwrap_or(ObjLit(Literal::Nil)),
+                        Object::Map(ref map) => {
+                            let key = Literal::String(Rc::new(id.lexeme.to_string()));
+                            map.borrow()
+                                .get(&key)
+                                .cloned()
+                                .unwrap_or(ObjLit(Literal::Nil))
+                        }
+                        _ => {
+                            return Err(RloxError::Runtime(
+                                brace.line,
+                                "Can only destructure fields of instances and maps".to_string(),
+                                format!("{}", val),
+                            ))
+                        }
+                    };
+
+                    self.env.define(id, field)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_const(&mut self, _stmt: &Stmt, id: &Token, init_expr: &Expr) -> Result<()> {
+        let val = init_expr.accept(self)?;
+        self.env.define_const(id, val)
+    }
+
     fn visit_block(&mut self, _stmt: &Stmt, body: &[Stmt]) -> Result<()> {
         let mut new_scope = self.create_scope();
         for stmt in body {
@@ -319,10 +538,47 @@ impl StmtVisitor<Result<()>> for Interpreter {
         Ok(())
     }
 
-    fn visit_while(&mut self, _stmt: &Stmt, cond: &Expr, body: &Stmt) -> Result<()> {
+    fn visit_while(
+        &mut self,
+        _stmt: &Stmt,
+        cond: &Expr,
+        body: &Stmt,
+        inc: Option<&Expr>,
+    ) -> Result<()> {
         while cond.accept(self)?.is_truthy() {
             match body.accept(self) {
                 Err(RloxError::Break(_)) => return Ok(()),
+                Err(RloxError::Continue(_)) => (),
+                Err(e) => return Err(e),
+                _ => (),
+            };
+
+            if let Some(inc) = inc {
+                inc.accept(self)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_for_in(
+        &mut self,
+        _stmt: &Stmt,
+        id: &Token,
+        iterable: &Expr,
+        body: &Stmt,
+    ) -> Result<()> {
+        let iterable = iterable.accept(self)?;
+        let mut iter = LoxIterator::new(self, iterable, id)?;
+
+        while let Some(item) = iter.next(self, id)? {
+            // closures capture the variable of their own iteration
+            let mut scope = self.create_scope();
+            scope.env.define(id, item)?;
+
+            match body.accept(&mut scope) {
+                Err(RloxError::Break(_)) => return Ok(()),
+                Err(RloxError::Continue(_)) => (),
                 Err(e) => return Err(e),
                 _ => (),
             };
@@ -336,18 +592,28 @@ impl StmtVisitor<Result<()>> for Interpreter {
         Err(RloxError::Break(token.line))
     }
 
+    fn visit_continue(&mut self, _stmt: &Stmt, token: &Token) -> Result<()> {
+        Err(RloxError::Continue(token.line))
+    }
+
     fn visit_func(
         &mut self,
         _stmt: &Stmt,
         name: &Token,
-        params: &[Token],
-        body: &Stmt,
+        params: &Rc<Params>,
+        body: &Rc<Body>,
     ) -> Result<()> {
         let f = Callable::new(&self.env, params, body, false);
-        self.env.define(name, Object::Func(f))
+        self.env.define(name, Object::func(f))
     }
 
     fn visit_return(&mut self, _stmt: &Stmt, keyword: &Token, val: Option<&Expr>) -> Result<()> {
+        if let Some(call @ Expr::Call(callee, paren, args, named)) = val {
+            if self.tail_calls.contains(call) {
+                return self.tail_call(keyword, callee, paren, args, named);
+            }
+        }
+
         let ret = match val {
             Some(expr) => expr.accept(self)?,
             None => ObjLit(Literal::Nil),
@@ -356,14 +622,26 @@ impl StmtVisitor<Result<()>> for Interpreter {
         Err(RloxError::Return(keyword.line, ret))
     }
 
+    fn visit_yield(&mut self, _stmt: &Stmt, keyword: &Token, _val: Option<&Expr>) -> Result<()> {
+        // functions containing a yield run as a Generator instead
+        Err(RloxError::Runtime(
+            keyword.line,
+            "Unexpected yield".to_string(),
+            keyword.lexeme.to_string(),
+        ))
+    }
+
     fn visit_class(
         &mut self,
         _stmt: &Stmt,
         name: &Token,
         parent: Option<&Expr>,
+        traits: &[Expr],
         methods: &[Stmt],
     ) -> Result<()> {
         let env = Env::from(&self.env);
+        let owner = class::next_owner();
+        OWNER.with(|id| env.define(id, ObjLit(Literal::Integer(owner as i64))))?;
 
         let super_class = if let Some(p) = parent {
             let class = match p.accept(self)? {
@@ -372,12 +650,12 @@ impl StmtVisitor<Result<()>> for Interpreter {
                     return Err(RloxError::Runtime(
                         name.line,
                         "Superclass must be a class".to_string(),
-                        name.lexeme.to_owned(),
+                        name.lexeme.to_string(),
                     ))
                 }
             };
 
-            env.define(&SUPER, Object::Class(Rc::clone(&class)))?;
+            SUPER.with(|id| env.define(id, Object::Class(Rc::clone(&class))))?;
 
             Some(class)
         } else {
@@ -385,20 +663,112 @@ impl StmtVisitor<Result<()>> for Interpreter {
         };
 
         let mut method_map = HashMap::with_capacity(methods.len());
+        let mut abstracts = Vec::new();
+        let mut fields = Vec::new();
         for method in methods {
             match method {
                 Stmt::Function(ref id, ref params, ref body) => {
                     let f = Callable::new(&env, params, body, id.lexeme.eq("init"));
 
-                    method_map.insert(id.lexeme.clone(), f);
+                    method_map.insert(id.lexeme, f);
+                }
+                Stmt::Abstract(ref id, _) => abstracts.push(id.lexeme),
+                Stmt::Declaration(ref id, ref init) => {
+                    fields.push((id.clone(), init.as_ref().map(|init| init.as_ref().clone())))
                 }
                 _ => unreachable!(),
             }
         }
 
-        let cls = Rc::new(LoxClass::new(name.lexeme.clone(), super_class, method_map));
+        let traits = self.mix_in(name, traits, &method_map)?;
+        let cls = Rc::new(LoxClass::new(
+            name.lexeme.to_string(),
+            owner,
+            super_class,
+            traits,
+            method_map,
+            abstracts,
+            FieldDecls::new(&env, fields),
+        ));
+
+        let missing = cls.unimplemented();
+        if !cls.is_abstract() && !missing.is_empty() {
+            return Err(RloxError::Runtime(
+                name.line,
+                format!(
+                    "{} must implement {}",
+                    cls,
+                    class::describe_unimplemented(&missing)
+                ),
+                name.lexeme.to_string(),
+            ));
+        }
+
         self.env.define(name, Object::Class(cls))
     }
+
+    fn visit_trait(&mut self, _stmt: &Stmt, name: &Token, methods: &[Stmt]) -> Result<()> {
+        let env = Env::from(&self.env);
+        let owner = class::next_owner();
+        OWNER.with(|id| env.define(id, ObjLit(Literal::Integer(owner as i64))))?;
+
+        let mut method_map = HashMap::with_capacity(methods.len());
+        let mut abstracts = Vec::new();
+        for method in methods {
+            match method {
+                Stmt::Function(ref id, ref params, ref body) => {
+                    let f = Callable::new(&env, params, body, false);
+
+                    method_map.insert(id.lexeme, f);
+                }
+                Stmt::Abstract(ref id, _) => abstracts.push(id.lexeme),
+                _ => unreachable!(),
+            }
+        }
+
+        let t = Rc::new(LoxTrait::new(
+            name.lexeme.to_string(),
+            owner,
+            method_map,
+            abstracts,
+        ));
+        self.env.define(name, Object::Trait(t))
+    }
+
+    fn visit_enum(&mut self, _stmt: &Stmt, name: &Token, variants: &[Token]) -> Result<()> {
+        let e = LoxEnum::new(name, variants);
+        self.env.define(name, Object::Enum(Rc::new(e)))
+    }
+
+    fn visit_match(
+        &mut self,
+        _stmt: &Stmt,
+        keyword: &Token,
+        subject: &Expr,
+        arms: &[(Pattern, Stmt)],
+    ) -> Result<()> {
+        let val = subject.accept(self)?;
+
+        for (pattern, body) in arms {
+            let mut bindings = Vec::new();
+            if !self.match_pattern(pattern, &val, &mut bindings)? {
+                continue;
+            }
+
+            let mut scope = self.create_scope();
+            for (id, val) in bindings {
+                scope.env.define(&id, val)?;
+            }
+
+            return body.accept(&mut scope);
+        }
+
+        Err(RloxError::Runtime(
+            keyword.line,
+            "No match arm matched value".to_string(),
+            format!("{}", val),
+        ))
+    }
 }
 
 impl Interpreter {
@@ -407,13 +777,139 @@ impl Interpreter {
             repl: false,
             env: Env::from(&self.env),
             locals: Rc::clone(&self.locals),
+            tail_calls: Rc::clone(&self.tail_calls),
         }
     }
 
+    // Evaluates the traits of a class. Two traits may only share a method
+    // if the class overrides it.
+    fn mix_in(
+        &mut self,
+        name: &Token,
+        traits: &[Expr],
+        methods: &HashMap<Symbol, Callable>,
+    ) -> Result<Vec<Rc<LoxTrait>>> {
+        let mut mixed: Vec<Rc<LoxTrait>> = Vec::with_capacity(traits.len());
+
+        for t in traits {
+            let t = match t.accept(self)? {
+                Object::Trait(ref t) => Rc::clone(t),
+                x => {
+                    return Err(RloxError::Runtime(
+                        name.line,
+                        "Can only mix in traits".to_string(),
+                        format!("{}", x),
+                    ))
+                }
+            };
+
+            for &method in t.method_names().filter(|m| !methods.contains_key(*m)) {
+                if let Some(other) = mixed.iter().find(|other| other.has_method(method)) {
+                    return Err(RloxError::Runtime(
+                        name.line,
+                        format!("{} is defined by both {} and {}", method, other, t),
+                        name.lexeme.to_string(),
+                    ));
+                }
+            }
+
+            mixed.push(t);
+        }
+
+        Ok(mixed)
+    }
+
+    // a property of any object, looked up without a cache
+    fn get_property(&self, obj: Object, prop: &Token) -> Result<Object> {
+        match obj {
+            Object::Generator(ref gen) if prop.lexeme == "next" => Ok(Generator::next_method(gen)),
+            Object::Enum(ref e) => LoxEnum::get(e, prop),
+            Object::Variant(ref v) => v.get(prop),
+            Object::Instance(ref inst) => self.get_prop(inst, prop, None),
+            _ => Err(RloxError::Runtime(
+                prop.line,
+                "Only instances have properties".to_string(),
+                prop.lexeme.to_string(),
+            )),
+        }
+    }
+
+    // #name properties are only visible to methods of the class or trait declaring them
+    fn get_prop(
+        &self,
+        inst: &LoxInstance,
+        prop: &Token,
+        cache: Option<&InlineCache>,
+    ) -> Result<Object> {
+        match prop.token_type {
+            TokenType::Private => inst.get_private(self.owner(prop)?, prop),
+            _ => inst.get_cached(prop, cache),
+        }
+    }
+
+    fn set_prop(
+        &self,
+        inst: &LoxInstance,
+        prop: &Token,
+        val: Object,
+        cache: Option<&InlineCache>,
+    ) -> Result<Object> {
+        match prop.token_type {
+            TokenType::Private => inst.set_private(self.owner(prop)?, prop, val),
+            _ => inst.set_cached(prop, val, cache),
+        }
+    }
+
+    // the class or trait whose method is running
+    fn owner(&self, prop: &Token) -> Result<usize> {
+        match OWNER.with(|id| self.env.get(id)) {
+            Ok(ObjLit(Literal::Integer(owner))) => Ok(owner as usize),
+            _ => Err(RloxError::Runtime(
+                prop.line,
+                "Private members are only accessible inside their class".to_string(),
+                prop.lexeme.to_string(),
+            )),
+        }
+    }
+
+    // Splits a list into its first count items and, with rest, the ones after them
+    fn unpack(
+        val: &Object,
+        count: usize,
+        rest: bool,
+        bracket: &Token,
+    ) -> Result<(Vec<Object>, Vec<Object>)> {
+        let list = match val {
+            Object::List(ref list) => list.borrow(),
+            _ => {
+                return Err(RloxError::Runtime(
+                    bracket.line,
+                    "Can only destructure a list".to_string(),
+                    format!("{}", val),
+                ))
+            }
+        };
+
+        if list.len() < count || (!rest && list.len() != count) {
+            return Err(RloxError::Runtime(
+                bracket.line,
+                format!(
+                    "Expected {}{} items to destructure but got {}",
+                    if rest { "at least " } else { "" },
+                    count,
+                    list.len()
+                ),
+                format!("{}", val),
+            ));
+        }
+
+        Ok((list[..count].to_vec(), list[count..].to_vec()))
+    }
+
     fn lookup_var(&self, id: &Token, expr: &Expr) -> Result<Object> {
         self.env.get_at(id, self.locals.get(expr).copied())
     }
-    fn err_near(&self, msg: &str, op: &Token, near: String) -> Result<Object> {
+    fn err_near<T>(&self, msg: &str, op: &Token, near: String) -> Result<T> {
         Err(RloxError::Runtime(op.line, msg.to_string(), near))
     }
 
@@ -422,17 +918,404 @@ impl Interpreter {
         Err(RloxError::Runtime(
             op.line,
             msg.to_string(),
-            op.lexeme.clone(),
+            op.lexeme.to_string(),
         ))
     }
 
-    fn call_dispatch(&mut self, callee: &Callable, paren: &Token, args: &[Expr]) -> Result<Object> {
-        if callee.arity() != args.len() {
+    fn binary_op(&self, lhs: Object, op: &Token, rhs: Object) -> Result<Object> {
+        use std::cmp::Ordering;
+
+        use crate::tokens::TokenType::*;
+
+        let result = match op.token_type {
+            Plus => match (lhs, rhs) {
+                // strings concatenate with the printed form of anything
+                (ObjLit(Literal::String(ref ls)), ref r) => {
+                    Literal::String(Rc::new(format!("{}{}", ls, r)))
+                }
+                (ref l, ObjLit(Literal::String(ref rs))) => {
+                    Literal::String(Rc::new(format!("{}{}", l, rs)))
+                }
+
+                (l, r) => match arithmetic(&l, &r, i64::checked_add, |l, r| l + r) {
+                    Some(n) => n,
+                    None => {
+                        return self.err_near(
+                            "Cannot add mixed types",
+                            op,
+                            format!("{:?} + {:?}", l, r),
+                        )
+                    }
+                },
+            },
+            Minus => match arithmetic(&lhs, &rhs, i64::checked_sub, |l, r| l - r) {
+                Some(n) => n,
+                None => {
+                    return self.err_near(
+                        "Cannot subtract non-numeric operands",
+                        op,
+                        format!("{:?} - {:?}", lhs, rhs),
+                    )
+                }
+            },
+
+            Star => match arithmetic(&lhs, &rhs, i64::checked_mul, |l, r| l * r) {
+                Some(n) => n,
+                None => {
+                    return self.err_near(
+                        "Cannot multiply non-numeric operands",
+                        op,
+                        format!("{:?} * {:?}", lhs, rhs),
+                    )
+                }
+            },
+
+            Slash | Percent if is_zero(&rhs) => {
+                return self.err_near(
+                    "Division by zero",
+                    op,
+                    format!("{} {} {}", lhs, op.lexeme, rhs),
+                )
+            }
+            // integer division truncates towards zero
+            Slash => match arithmetic(&lhs, &rhs, i64::checked_div, |l, r| l / r) {
+                Some(n) => n,
+                None => {
+                    return self.err_near(
+                        "Cannot divide non-numerics",
+                        op,
+                        format!("{:?} / {:?}", lhs, rhs),
+                    )
+                }
+            },
+            Percent => match arithmetic(&lhs, &rhs, |l, r| Some(l.wrapping_rem(r)), |l, r| l % r) {
+                Some(n) => n,
+                None => {
+                    return self.err_near(
+                        "Cannot take remainder of non-numerics",
+                        op,
+                        format!("{:?} % {:?}", lhs, rhs),
+                    )
+                }
+            },
+
+            // negative integer exponents give a float
+            StarStar => match arithmetic(&lhs, &rhs, checked_pow, f64::powf) {
+                Some(n) => n,
+                None => {
+                    return self.err_near(
+                        "Cannot exponentiate non-numerics",
+                        op,
+                        format!("{:?} ** {:?}", lhs, rhs),
+                    )
+                }
+            },
+
+            Amp | Pipe | Caret | LessLess | GreaterGreater => {
+                let (l, r) = match (&lhs, &rhs) {
+                    (ObjLit(l), ObjLit(r)) if l.as_i64().is_some() && r.as_i64().is_some() => {
+                        (l.as_i64().unwrap(), r.as_i64().unwrap())
+                    }
+                    _ => {
+                        return self.err_near(
+                            "Bitwise operands must be integers",
+                            op,
+                            format!("{:?} {} {:?}", lhs, op.lexeme, rhs),
+                        )
+                    }
+                };
+
+                match op.token_type {
+                    Amp => Literal::Integer(l & r),
+                    Pipe => Literal::Integer(l | r),
+                    Caret => Literal::Integer(l ^ r),
+                    _ if !(0..64).contains(&r) => {
+                        return self.err_near(
+                            "Shift amount must be between 0 and 63",
+                            op,
+                            format!("{:?} {} {:?}", lhs, op.lexeme, rhs),
+                        )
+                    }
+                    // bits shifted past the sign are an error rather than lost
+                    LessLess => match l.checked_shl(r as u32).filter(|shifted| shifted >> r == l) {
+                        Some(shifted) => Literal::Integer(shifted),
+                        None => {
+                            return self.err_near(
+                                "Shift overflows an integer",
+                                op,
+                                format!("{} << {}", lhs, rhs),
+                            )
+                        }
+                    },
+                    _ => Literal::Integer(l >> r),
+                }
+            }
+
+            Greater | GreaterEqual | Less | LessEqual => match lhs.partial_cmp(&rhs) {
+                Some(Ordering::Less) => Literal::Boolean(op.in_types(&[Less, LessEqual])),
+                Some(Ordering::Equal) => Literal::Boolean(op.in_types(&[LessEqual, GreaterEqual])),
+                Some(Ordering::Greater) => Literal::Boolean(op.in_types(&[Greater, GreaterEqual])),
+                None => {
+                    return self.err_near(
+                        "Cannot compare types",
+                        op,
+                        format!("{:?} (compare) {:?}", lhs, rhs),
+                    )
+                }
+            },
+
+            EqualEqual => Literal::Boolean(lhs.eq(&rhs)),
+            BangEqual => Literal::Boolean(lhs.ne(&rhs)),
+
+            _ => return self.err_op("Invalid binary operator", op),
+        };
+
+        Ok(ObjLit(result))
+    }
+
+    // map keys are looked up by value so they have to be literals
+    fn map_key(&self, token: &Token, key: Object) -> Result<Literal> {
+        match key {
+            ObjLit(lit) => Ok(lit),
+            x => Err(RloxError::Runtime(
+                token.line,
+                "Map keys must be numbers, strings, booleans or nil".to_string(),
+                format!("{}", x),
+            )),
+        }
+    }
+
+    fn list_index(&self, len: usize, bracket: &Token, index: Object) -> Result<usize> {
+        let idx = match index {
+            ObjLit(Literal::Integer(i)) => i,
+            x => {
+                return Err(RloxError::Runtime(
+                    bracket.line,
+                    "List index must be an integer".to_string(),
+                    format!("{}", x),
+                ))
+            }
+        };
+
+        if idx < 0 || idx as usize >= len {
+            return Err(RloxError::Runtime(
+                bracket.line,
+                format!("List index out of bounds for length {}", len),
+                idx.to_string(),
+            ));
+        }
+
+        Ok(idx as usize)
+    }
+
+    // Missing map keys give nil
+    fn index_get(&self, obj: &Object, bracket: &Token, index: Object) -> Result<Object> {
+        match obj {
+            Object::List(ref list) => {
+                let idx = self.list_index(list.borrow().len(), bracket, index)?;
+                Ok(list.borrow()[idx].clone())
+            }
+            Object::Map(ref map) => {
+                let key = self.map_key(bracket, index)?;
+                Ok(map
+                    .borrow()
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or(ObjLit(Literal::Nil)))
+            }
+            x => Err(RloxError::Runtime(
+                bracket.line,
+                "Only lists and maps can be indexed".to_string(),
+                format!("{}", x),
+            )),
+        }
+    }
+
+    fn index_set(&self, obj: &Object, bracket: &Token, index: Object, val: Object) -> Result<()> {
+        match obj {
+            Object::List(ref list) => {
+                let idx = self.list_index(list.borrow().len(), bracket, index)?;
+                list.borrow_mut()[idx] = val;
+            }
+            Object::Map(ref map) => {
+                let key = self.map_key(bracket, index)?;
+                map.borrow_mut().insert(key, val);
+            }
+            x => {
+                return Err(RloxError::Runtime(
+                    bracket.line,
+                    "Only lists and maps can be indexed".to_string(),
+                    format!("{}", x),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    // Checks val against pattern, collecting the variables it binds.
+    pub(crate) fn match_pattern(
+        &mut self,
+        pattern: &Pattern,
+        val: &Object,
+        bindings: &mut Vec<(Token, Object)>,
+    ) -> Result<bool> {
+        match pattern {
+            Pattern::Wildcard => Ok(true),
+            Pattern::Value(ref expected) => Ok(*val == expected.accept(self)?),
+            Pattern::Literal(ref lit) => Ok(*val == ObjLit(lit.literal.clone().unwrap())),
+            Pattern::Binding(ref id) => {
+                bindings.push((id.clone(), val.clone()));
+                Ok(true)
+            }
+            Pattern::Alternatives(ref alts) => {
+                for alt in alts {
+                    if self.match_pattern(alt, val, bindings)? {
+                        return Ok(true);
+                    }
+                }
+
+                Ok(false)
+            }
+            Pattern::Class(ref class_expr, ref fields) => {
+                let (line, name) = match class_expr {
+                    Expr::Identifier(ref id) => (id.line, id.lexeme.as_str()),
+                    _ => (0, ""),
+                };
+
+                let class = match class_expr.accept(self)? {
+                    Object::Class(class) => class,
+                    x => {
+                        return Err(RloxError::Runtime(
+                            line,
+                            "Class patterns need a class".to_string(),
+                            format!("{} is {}", name, x),
+                        ))
+                    }
+                };
+
+                let inst = match val {
+                    Object::Instance(inst) if inst.class().is_subclass_of(&class) => inst,
+                    _ => return Ok(false),
+                };
+
+                // sub-patterns match the fields in the order they were set
+                let values = inst.field_values();
+                if fields.len() > values.len() {
+                    return Ok(false);
+                }
+
+                for (field, val) in fields.iter().zip(values.iter()) {
+                    if !self.match_pattern(field, val, bindings)? {
+                        return Ok(false);
+                    }
+                }
+
+                Ok(true)
+            }
+            Pattern::List(ref items, ref rest) => {
+                let list = match val {
+                    Object::List(list) => list.borrow().to_vec(),
+                    _ => return Ok(false),
+                };
+
+                if list.len() < items.len() || (rest.is_none() && list.len() != items.len()) {
+                    return Ok(false);
+                }
+
+                for (item, val) in items.iter().zip(list.iter()) {
+                    if !self.match_pattern(item, val, bindings)? {
+                        return Ok(false);
+                    }
+                }
+
+                match rest {
+                    Some(rest) => {
+                        let tail = Object::list(list[items.len()..].to_vec());
+                        self.match_pattern(rest, &tail, bindings)
+                    }
+                    None => Ok(true),
+                }
+            }
+        }
+    }
+
+    fn call_object(
+        &mut self,
+        callee: Object,
+        paren: &Token,
+        args: &[Expr],
+        named: &[(Token, Expr)],
+    ) -> Result<Object> {
+        match callee {
+            Object::Func(ref f) => self.call_dispatch(f, paren, args, named),
+            Object::Class(ref cls) => self.call_dispatch(&Callable::init(cls), paren, args, named),
+            x => self.err_near(
+                "Can only call functions and classes",
+                paren,
+                format!("{:?}", x),
+            ),
+        }
+    }
+
+    // 'return f(x);' where the resolver found f(x) in tail position. Lox
+    // functions are handed back to the caller's loop in LoxFunction::call
+    // and anything else is called as usual.
+    fn tail_call(
+        &mut self,
+        keyword: &Token,
+        callee: &Expr,
+        paren: &Token,
+        args: &[Expr],
+        named: &[(Token, Expr)],
+    ) -> Result<()> {
+        let callee = callee.accept(self)?;
+        let func = match callee {
+            Object::Func(ref f) => match **f {
+                Callable::Runtime(ref func) => Some(func.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let Some(func) = func else {
+            let ret = self.call_object(callee, paren, args, named)?;
+            return Err(RloxError::Return(keyword.line, ret));
+        };
+
+        let (args, named) = self.eval_args(func.arity(), paren, args, named)?;
+        Err(RloxError::TailCall(Box::new(TailCall {
+            func,
+            paren: paren.clone(),
+            args,
+            named,
+        })))
+    }
+
+    fn call_dispatch(
+        &mut self,
+        callee: &Callable,
+        paren: &Token,
+        args: &[Expr],
+        named: &[(Token, Expr)],
+    ) -> Result<Object> {
+        let (params, named_params) = self.eval_args(callee.arity(), paren, args, named)?;
+        callee.call_named(self, paren, &params, &named_params)
+    }
+
+    fn eval_args(
+        &mut self,
+        arity: Arity,
+        paren: &Token,
+        args: &[Expr],
+        named: &[(Token, Expr)],
+    ) -> Result<Args> {
+        if !arity.accepts(args.len() + named.len()) {
             return self.err_near(
                 &format!(
                     "expected {} arguments but got {}",
-                    callee.arity(),
-                    args.len()
+                    arity,
+                    args.len() + named.len()
                 ),
                 paren,
                 "".to_string(),
@@ -444,14 +1327,20 @@ impl Interpreter {
             params.push(arg.accept(self)?);
         }
 
-        callee.call(self, &params)
+        let mut named_params = Vec::with_capacity(named.len());
+        for (name, arg) in named {
+            named_params.push((name.clone(), arg.accept(self)?));
+        }
+
+        Ok((params, named_params))
     }
 
     pub fn new(repl: bool) -> Self {
         Self {
             repl,
-            env: Env::new(),
+            env: natives::globals(),
             locals: Rc::new(HashMap::new()),
+            tail_calls: Rc::new(HashSet::new()),
         }
     }
 
@@ -460,6 +1349,7 @@ impl Interpreter {
             repl: self.repl,
             env,
             locals: Rc::clone(&self.locals),
+            tail_calls: Rc::clone(&self.tail_calls),
         }
     }
 
@@ -468,4 +1358,46 @@ impl Interpreter {
             .expect("Should be the only mutable ref")
             .insert(expr.clone(), idx);
     }
+
+    pub(crate) fn tail_call_at(&mut self, call: &Expr) {
+        Rc::get_mut(&mut self.tail_calls)
+            .expect("Should be the only mutable ref")
+            .insert(call.clone());
+    }
+}
+
+// Applies an arithmetic operator to two numbers. Integers use exact
+// arithmetic and are promoted to floats if the result overflows;
+// any float operand makes the whole operation a float one.
+fn arithmetic(
+    lhs: &Object,
+    rhs: &Object,
+    int_op: fn(i64, i64) -> Option<i64>,
+    float_op: fn(f64, f64) -> f64,
+) -> Option<Literal> {
+    match (lhs, rhs) {
+        (ObjLit(Literal::Integer(l)), ObjLit(Literal::Integer(r))) => {
+            Some(int_op(*l, *r).map_or_else(
+                || Literal::Number(float_op(*l as f64, *r as f64)),
+                Literal::Integer,
+            ))
+        }
+        (ObjLit(l), ObjLit(r)) if l.is_numeric() && r.is_numeric() => {
+            Some(Literal::Number(float_op(l.as_f64()?, r.as_f64()?)))
+        }
+        _ => None,
+    }
+}
+
+fn checked_pow(base: i64, exp: i64) -> Option<i64> {
+    use std::convert::TryFrom;
+
+    base.checked_pow(u32::try_from(exp).ok()?)
+}
+
+fn is_zero(val: &Object) -> bool {
+    match val {
+        ObjLit(lit) => lit.as_f64() == Some(0.0),
+        _ => false,
+    }
 }
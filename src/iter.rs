@@ -0,0 +1,118 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    class::LoxInstance,
+    error::{Result, RloxError},
+    generator::Generator,
+    interpreter::Interpreter,
+    map::LoxMap,
+    object::{Items, Object},
+    symbol::Symbol,
+    tokens::{Literal, Token},
+};
+
+// The state of a for-in loop. Nothing is copied up front;
+// lists and maps are read as the loop advances.
+#[derive(Debug)]
+pub(crate) enum LoxIterator {
+    List(Rc<RefCell<Items>>, usize),
+    // yields the keys
+    Map(Rc<RefCell<LoxMap>>, usize),
+    // yields single character strings; the index is a byte offset
+    Str(Rc<String>, usize),
+    Range(i64, i64),
+    // any instance with a next() method, which returns nil when done
+    Instance(LoxInstance),
+    Generator(Rc<RefCell<Generator>>),
+}
+
+impl LoxIterator {
+    pub(crate) fn new(interpreter: &Interpreter, obj: Object, token: &Token) -> Result<Self> {
+        match obj {
+            Object::List(list) => Ok(LoxIterator::List(list, 0)),
+            Object::Map(map) => Ok(LoxIterator::Map(map, 0)),
+            Object::Literal(Literal::String(s)) => Ok(LoxIterator::Str(s, 0)),
+            Object::Range(range) => Ok(LoxIterator::Range(range.start, range.end)),
+            Object::Generator(gen) => Ok(LoxIterator::Generator(gen)),
+            Object::Instance(inst) if inst.class().find_method("next".into()).is_some() => {
+                Ok(LoxIterator::Instance(inst))
+            }
+            // iter() may hand back an iterator instance or anything else iterable
+            Object::Instance(inst) if inst.class().find_method("iter".into()).is_some() => {
+                match call_method(interpreter, &inst, "iter", token)? {
+                    Object::Instance(ref it) if it.class().find_method("next".into()).is_none() => {
+                        Err(RloxError::Runtime(
+                            token.line,
+                            "iter() must return an object with a next() method".to_string(),
+                            format!("{}", it),
+                        ))
+                    }
+                    it => LoxIterator::new(interpreter, it, token),
+                }
+            }
+            x => Err(RloxError::Runtime(
+                token.line,
+                "Object is not iterable".to_string(),
+                format!("{}", x),
+            )),
+        }
+    }
+
+    pub(crate) fn next(
+        &mut self,
+        interpreter: &Interpreter,
+        token: &Token,
+    ) -> Result<Option<Object>> {
+        let item = match self {
+            LoxIterator::List(ref list, ref mut i) => {
+                let item = list.borrow().get(*i).cloned();
+                *i += 1;
+                item
+            }
+            LoxIterator::Map(ref map, ref mut i) => {
+                let key = map.borrow().key_at(*i).cloned();
+                *i += 1;
+                key.map(Object::Literal)
+            }
+            LoxIterator::Str(ref s, ref mut i) => {
+                let ch = s[*i..].chars().next();
+                *i += ch.map_or(0, |ch| ch.len_utf8());
+                ch.map(|ch| Object::string(ch.to_string()))
+            }
+            LoxIterator::Range(ref mut start, end) if *start < *end => {
+                *start += 1;
+                Some(Object::Literal(Literal::Integer(*start - 1)))
+            }
+            LoxIterator::Range(_, _) => None,
+            LoxIterator::Instance(ref inst) => match call_method(interpreter, inst, "next", token)?
+            {
+                Object::Literal(Literal::Nil) => None,
+                item => Some(item),
+            },
+            LoxIterator::Generator(ref gen) => Generator::next(gen, interpreter, token)?,
+        };
+
+        Ok(item)
+    }
+}
+
+fn call_method(
+    interpreter: &Interpreter,
+    inst: &LoxInstance,
+    name: &str,
+    token: &Token,
+) -> Result<Object> {
+    let name = Token {
+        lexeme: Symbol::intern(name),
+        ..token.clone()
+    };
+
+    match inst.get(&name)? {
+        Object::Func(ref f) if f.arity().accepts(0) => f.call(interpreter, token, &[]),
+        _ => Err(RloxError::Runtime(
+            token.line,
+            format!("{}() must be a method without parameters", name.lexeme),
+            format!("{}", inst),
+        )),
+    }
+}
@@ -2,34 +2,55 @@
 //#![allow(dead_code)]
 
 mod class;
+mod enums;
 mod env;
 mod error;
 mod expr;
 mod functions;
+mod generator;
 mod interpreter;
+mod iter;
+mod map;
+mod natives;
 mod object;
+mod optimizer;
 mod parser;
+mod pattern;
 mod resolver;
 mod runner;
 mod scanner;
+mod shape;
 mod stmt;
+mod symbol;
 mod tokens;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut run = runner::Runner {};
+    let mut run = runner::Runner::new();
+
+    let mut args = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--no-optimize" => run.optimize = false,
+            "--time" => run.timings = Some(runner::Timings::default()),
+            _ => args.push(arg),
+        }
+    }
 
-    let args = std::env::args().collect::<Vec<_>>();
     let res = {
         match args.len() {
-            1 => run.prompt(),
-            2 => run.file(&args[1]),
+            0 => run.prompt(),
+            1 => run.file(&args[0]),
             _ => {
-                eprintln!("Usage: rlox [script]");
+                eprintln!("Usage: rlox [--no-optimize] [--time] [script]");
                 std::process::exit(64);
             }
         }
     };
 
+    if let Some(ref timings) = run.timings {
+        eprintln!("{}", timings);
+    }
+
     if let Err(e) = res {
         eprintln!("{}", e);
         std::process::exit(1);
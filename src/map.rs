@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+
+use crate::{object::Object, tokens::Literal};
+
+// Maps keep their insertion order so that iterating over them is predictable.
+// Only literals can be used as keys.
+#[derive(Debug, Default)]
+pub(crate) struct LoxMap {
+    entries: Vec<(Literal, Object)>,
+    index: HashMap<Literal, usize>,
+}
+
+impl LoxMap {
+    pub(crate) fn get(&self, key: &Literal) -> Option<&Object> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub(crate) fn insert(&mut self, key: Literal, val: Object) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = val,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, val));
+            }
+        }
+    }
+
+    pub(crate) fn key_at(&self, i: usize) -> Option<&Literal> {
+        self.entries.get(i).map(|(key, _)| key)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &(Literal, Object)> {
+        self.entries.iter()
+    }
+}
+
+impl std::fmt::Display for LoxMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, val)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", key, val)?;
+        }
+        write!(f, "}}")
+    }
+}
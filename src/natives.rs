@@ -0,0 +1,183 @@
+use std::rc::Rc;
+
+use crate::{
+    class::LoxInstance,
+    env::Env,
+    error::{Result, RloxError},
+    functions::{Callable, NativeFunction},
+    interpreter::Interpreter,
+    object::Object,
+    symbol::Symbol,
+    tokens::{Literal, Token, TokenType},
+};
+
+// Functions available in the global scope
+pub(crate) fn globals() -> Rc<Env> {
+    let env = Env::new();
+
+    define(&env, NativeFunction::new("freeze", 1, freeze));
+    define(&env, NativeFunction::new("type", 1, type_of));
+    define(&env, NativeFunction::new("isinstance", 2, isinstance));
+    define(&env, NativeFunction::new("classof", 1, classof));
+    define(&env, NativeFunction::new("fields", 1, fields));
+    define(&env, NativeFunction::new("methods", 1, methods));
+    define(&env, NativeFunction::new("hasattr", 2, hasattr));
+    define(&env, NativeFunction::new("getattr", 2, getattr));
+    define(&env, NativeFunction::new("setattr", 3, setattr));
+    define(&env, NativeFunction::new("arity", 1, arity));
+
+    env
+}
+
+fn define(env: &Rc<Env>, func: NativeFunction) {
+    let name = Token {
+        lexeme: Symbol::intern(func.name()),
+        ..Token::default()
+    };
+
+    env.define(&name, Object::func(Callable::Native(func)))
+        .expect("Failed to define native function");
+}
+
+// freeze(obj) stops any more fields being set on obj and returns it
+fn freeze(_: &Interpreter, paren: &Token, args: &[Object]) -> Result<Object> {
+    match args[0] {
+        Object::Instance(ref inst) => {
+            inst.freeze();
+            Ok(args[0].clone())
+        }
+        ref x => Err(runtime(paren, "Only instances can be frozen", x)),
+    }
+}
+
+// type(v) names the kind of value v is: "int", "string", "instance", ...
+fn type_of(_: &Interpreter, _: &Token, args: &[Object]) -> Result<Object> {
+    let name = match args[0] {
+        Object::Literal(ref lit) => match lit {
+            Literal::Nil => "nil",
+            Literal::Boolean(_) => "bool",
+            Literal::Integer(_) => "int",
+            Literal::Number(_) => "number",
+            Literal::String(_) => "string",
+        },
+        Object::Func(_) => "function",
+        Object::Class(_) => "class",
+        Object::Trait(_) => "trait",
+        Object::Instance(_) => "instance",
+        Object::List(_) => "list",
+        Object::Map(_) => "map",
+        Object::Range(..) => "range",
+        Object::Generator(_) => "generator",
+        Object::Enum(_) => "enum",
+        Object::Variant(_) => "variant",
+    };
+
+    Ok(string(name))
+}
+
+// isinstance(v, cls) is true if v is an instance of cls or one of its subclasses
+fn isinstance(_: &Interpreter, paren: &Token, args: &[Object]) -> Result<Object> {
+    let class = match args[1] {
+        Object::Class(ref class) => class,
+        ref x => return Err(runtime(paren, "isinstance expects a class", x)),
+    };
+
+    let result = match args[0] {
+        Object::Instance(ref inst) => inst.class().is_subclass_of(class),
+        _ => false,
+    };
+
+    Ok(Object::Literal(Literal::Boolean(result)))
+}
+
+fn classof(_: &Interpreter, paren: &Token, args: &[Object]) -> Result<Object> {
+    let inst = instance(paren, &args[0])?;
+    Ok(Object::Class(Rc::clone(inst.class())))
+}
+
+// fields(inst) lists the names of the public fields set on inst
+fn fields(_: &Interpreter, paren: &Token, args: &[Object]) -> Result<Object> {
+    let inst = instance(paren, &args[0])?;
+    Ok(strings(inst.field_names()))
+}
+
+// methods(cls) lists the methods of a class, or of an instance's class
+fn methods(_: &Interpreter, paren: &Token, args: &[Object]) -> Result<Object> {
+    let class = match args[0] {
+        Object::Class(ref class) => class,
+        Object::Instance(ref inst) => inst.class(),
+        ref x => return Err(runtime(paren, "methods expects a class or instance", x)),
+    };
+
+    Ok(strings(class.method_names()))
+}
+
+fn hasattr(_: &Interpreter, paren: &Token, args: &[Object]) -> Result<Object> {
+    let inst = instance(paren, &args[0])?;
+    let name = attr(paren, &args[1])?;
+    Ok(Object::Literal(Literal::Boolean(inst.get(&name).is_ok())))
+}
+
+fn getattr(_: &Interpreter, paren: &Token, args: &[Object]) -> Result<Object> {
+    let inst = instance(paren, &args[0])?;
+    inst.get(&attr(paren, &args[1])?)
+}
+
+fn setattr(_: &Interpreter, paren: &Token, args: &[Object]) -> Result<Object> {
+    let inst = instance(paren, &args[0])?;
+    inst.set(&attr(paren, &args[1])?, args[2].clone())
+}
+
+// arity(fn) is the number of arguments fn takes. When that can vary it is
+// a list of the least and most, with nil for no limit.
+fn arity(_: &Interpreter, paren: &Token, args: &[Object]) -> Result<Object> {
+    let arity = match args[0] {
+        Object::Func(ref f) => f.arity(),
+        Object::Class(ref class) => Callable::init(class).arity(),
+        ref x => return Err(runtime(paren, "arity expects a function or class", x)),
+    };
+
+    let count = |n: usize| Object::Literal(Literal::Integer(n as i64));
+    Ok(match arity.max {
+        Some(max) if max == arity.min => count(max),
+        Some(max) => Object::list(vec![count(arity.min), count(max)]),
+        None => Object::list(vec![count(arity.min), Object::Literal(Literal::Nil)]),
+    })
+}
+
+fn instance<'a>(paren: &Token, obj: &'a Object) -> Result<&'a LoxInstance> {
+    match obj {
+        Object::Instance(ref inst) => Ok(inst),
+        x => Err(runtime(paren, "Expected an instance", x)),
+    }
+}
+
+// An attribute name given as a string, as a token for the instance lookups.
+// #names stay private, as they would be from outside the class.
+fn attr(paren: &Token, obj: &Object) -> Result<Token> {
+    match obj {
+        Object::Literal(Literal::String(ref name)) => Ok(Token {
+            token_type: if name.starts_with('#') {
+                TokenType::Private
+            } else {
+                TokenType::Ident
+            },
+            lexeme: Symbol::intern(name),
+            line: paren.line,
+            ..Token::default()
+        }),
+        x => Err(runtime(paren, "Attribute names must be strings", x)),
+    }
+}
+
+fn string(s: &str) -> Object {
+    Object::string(s.to_string())
+}
+
+fn strings(items: Vec<String>) -> Object {
+    Object::list(items.into_iter().map(Object::string).collect())
+}
+
+fn runtime(paren: &Token, msg: &str, obj: &Object) -> RloxError {
+    RloxError::Runtime(paren.line, msg.to_string(), format!("{}", obj))
+}
@@ -1,21 +1,91 @@
-use std::rc::Rc;
+use std::{cell::RefCell, rc::Rc};
 
-use crate::{class::LoxClass, functions::Callable};
-use crate::{class::LoxInstance, tokens};
+use crate::{
+    class::LoxClass,
+    enums::{LoxEnum, Variant},
+    functions::Callable,
+    generator::Generator,
+    map::LoxMap,
+};
+use crate::{
+    class::{LoxInstance, LoxTrait},
+    tokens,
+};
 
+// Values are cloned constantly, so every variant other than a literal holds
+// a single pointer. Together with the literal's tag that keeps an Object at
+// 16 bytes.
 #[derive(Debug, Clone)]
 pub(crate) enum Object {
     Literal(tokens::Literal),
-    Func(Callable),
+    Func(Rc<Callable>),
     Class(Rc<LoxClass>),
+    Trait(Rc<LoxTrait>),
     Instance(LoxInstance),
+    List(Rc<RefCell<Items>>),
+    Map(Rc<RefCell<LoxMap>>),
+    // start..end, excluding end
+    Range(Rc<std::ops::Range<i64>>),
+    Generator(Rc<RefCell<Generator>>),
+    Enum(Rc<LoxEnum>),
+    Variant(Rc<Variant>),
+}
+
+const _: () = assert!(std::mem::size_of::<Object>() == 16);
+
+// The items of a list
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Items(Vec<Object>);
+
+impl std::ops::Deref for Items {
+    type Target = Vec<Object>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for Items {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for Items {
+    fn drop(&mut self) {
+        free(std::mem::take(&mut self.0));
+    }
+}
+
+// Drops values one at a time. Lists and instances which are no longer
+// shared hand over their contents instead of dropping them recursively, so
+// freeing a long chain of them cannot overflow the stack.
+pub(crate) fn free(mut pending: Vec<Object>) {
+    while let Some(obj) = pending.pop() {
+        match obj {
+            Object::List(list) => {
+                if let Ok(items) = Rc::try_unwrap(list) {
+                    pending.append(&mut items.into_inner());
+                }
+            }
+            Object::Instance(inst) => inst.release(&mut pending),
+            _ => (),
+        }
+    }
 }
 
 impl std::cmp::PartialEq for Object {
     fn eq(&self, other: &Self) -> bool {
         use Object::Literal as ObjLit;
         match (self, other) {
-            (&ObjLit(ref lhs), &ObjLit(ref rhs)) => lhs.eq(rhs),
+            (ObjLit(lhs), ObjLit(rhs)) => lhs.eq(rhs),
+            (Object::List(lhs), Object::List(rhs)) => Rc::ptr_eq(lhs, rhs),
+            (Object::Map(lhs), Object::Map(rhs)) => Rc::ptr_eq(lhs, rhs),
+            (Object::Class(lhs), Object::Class(rhs)) => Rc::ptr_eq(lhs, rhs),
+            (Object::Enum(lhs), Object::Enum(rhs)) => Rc::ptr_eq(lhs, rhs),
+            (Object::Variant(lhs), Object::Variant(rhs)) => Rc::ptr_eq(lhs, rhs),
+            (Object::Range(lhs), Object::Range(rhs)) => lhs == rhs,
+            (Object::Generator(lhs), Object::Generator(rhs)) => Rc::ptr_eq(lhs, rhs),
             _ => false,
         }
     }
@@ -25,13 +95,25 @@ impl std::cmp::PartialOrd for Object {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         use Object::Literal as ObjLit;
         match (self, other) {
-            (&ObjLit(ref lhs), &ObjLit(ref rhs)) => lhs.partial_cmp(rhs),
+            (ObjLit(lhs), ObjLit(rhs)) => lhs.partial_cmp(rhs),
             _ => None,
         }
     }
 }
 
 impl Object {
+    pub fn list(items: Vec<Object>) -> Self {
+        Object::List(Rc::new(RefCell::new(Items(items))))
+    }
+
+    pub fn func(func: Callable) -> Self {
+        Object::Func(Rc::new(func))
+    }
+
+    pub fn string(s: String) -> Self {
+        Object::Literal(tokens::Literal::String(Rc::new(s)))
+    }
+
     // Lox follow Ruby's rule: false and nil are falsey
     // otherwise depends on literal
     pub fn is_truthy(&self) -> bool {
@@ -40,6 +122,7 @@ impl Object {
             Object::Literal(ref lit) => match *lit {
                 Nil => false,
                 Boolean(b) => b,
+                Integer(i) => i != 0,
                 Number(n) => n != 0.0,
                 String(ref s) => !s.is_empty(),
             },
@@ -54,7 +137,51 @@ impl std::fmt::Display for Object {
             Object::Literal(ref lit) => write!(f, "{}", lit),
             Object::Func(_) => write!(f, "<function>"),
             Object::Class(ref cls) => write!(f, "{}", cls),
+            Object::Trait(ref t) => write!(f, "{}", t),
             Object::Instance(ref i) => write!(f, "{}", i),
+            Object::List(ref list) => write_once(f, Rc::as_ptr(list).cast(), "[...]", |f| {
+                write!(f, "[")?;
+                for (i, item) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }),
+            Object::Map(ref map) => write_once(f, Rc::as_ptr(map).cast(), "{...}", |f| {
+                write!(f, "{}", map.borrow())
+            }),
+            Object::Range(ref range) => write!(f, "{}..{}", range.start, range.end),
+            Object::Generator(_) => write!(f, "<generator>"),
+            Object::Enum(ref e) => write!(f, "{}", e),
+            Object::Variant(ref v) => write!(f, "{}", v),
         }
     }
 }
+
+// the lists and maps being printed, innermost last
+thread_local! {
+    static PRINTING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+// Writes a list or map with body, or just cycle if it is already being
+// printed further out, so one containing itself does not recurse forever
+fn write_once<F>(
+    f: &mut std::fmt::Formatter<'_>,
+    ptr: *const (),
+    cycle: &str,
+    body: F,
+) -> std::fmt::Result
+where
+    F: FnOnce(&mut std::fmt::Formatter<'_>) -> std::fmt::Result,
+{
+    if PRINTING.with(|printing| printing.borrow().contains(&ptr)) {
+        return write!(f, "{}", cycle);
+    }
+
+    PRINTING.with(|printing| printing.borrow_mut().push(ptr));
+    let res = body(f);
+    PRINTING.with(|printing| printing.borrow_mut().pop());
+    res
+}
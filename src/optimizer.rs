@@ -0,0 +1,452 @@
+use std::rc::Rc;
+
+use crate::{
+    expr::{Expr, Visitor as ExprVisitor},
+    interpreter::Interpreter,
+    object::Object,
+    pattern::Pattern,
+    shape::InlineCache,
+    stmt::{Body, Destructure, Params, Stmt, Visitor as StmtVisitor},
+    symbol::Symbol,
+    tokens::{Literal, Token, TokenType},
+};
+
+// Rewrites statements before they are resolved: constant expressions are
+// folded, branches on constants are replaced by the branch taken and code
+// which can never run is dropped. Statements containing a yield are never
+// dropped, since that would stop their function being a generator.
+pub(crate) struct Optimizer {
+    // evaluates expressions made only of literals
+    folder: Interpreter,
+}
+
+impl Optimizer {
+    pub(crate) fn new() -> Self {
+        Self {
+            folder: Interpreter::new(false),
+        }
+    }
+
+    pub(crate) fn optimize(&mut self, stmt: &Stmt) -> Stmt {
+        stmt.accept(self)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Expr {
+        expr.accept(self)
+    }
+
+    fn boxed(&mut self, expr: &Expr) -> Box<Expr> {
+        Box::new(expr.accept(self))
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> Vec<Expr> {
+        exprs.iter().map(|e| e.accept(self)).collect()
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> Vec<Stmt> {
+        stmts.iter().map(|s| s.accept(self)).collect()
+    }
+
+    fn params(&mut self, params: &Params) -> Params {
+        Params {
+            list: params
+                .list
+                .iter()
+                .map(|(name, default)| (name.clone(), default.as_ref().map(|d| self.expr(d))))
+                .collect(),
+            rest: params.rest.clone(),
+        }
+    }
+
+    // Replaces an expression whose operands are all literals with its value.
+    // Anything that would fail is left for the interpreter to report.
+    fn fold(&mut self, expr: Expr, op: &Token) -> Expr {
+        match expr.accept(&mut self.folder) {
+            Ok(Object::Literal(lit)) => literal(lit, op),
+            _ => expr,
+        }
+    }
+}
+
+fn literal(lit: Literal, at: &Token) -> Expr {
+    let token_type = match lit {
+        Literal::Nil => TokenType::Nil,
+        Literal::Boolean(true) => TokenType::True,
+        Literal::Boolean(false) => TokenType::False,
+        Literal::Integer(_) | Literal::Number(_) => TokenType::Number,
+        Literal::String(_) => TokenType::StringLiteral,
+    };
+
+    Expr::Literal(Token {
+        token_type,
+        lexeme: Symbol::intern(&lit.to_string()),
+        literal: Some(lit),
+        ..at.clone()
+    })
+}
+
+// The value of a literal expression, if it is one
+fn constant(expr: &Expr) -> Option<Object> {
+    match expr {
+        Expr::Literal(ref lit) => lit.literal.clone().map(Object::Literal),
+        _ => None,
+    }
+}
+
+// break, continue and return end a block early
+fn is_jump(stmt: &Stmt) -> bool {
+    matches!(
+        stmt,
+        Stmt::Break(_) | Stmt::Continue(_) | Stmt::Return(_, _)
+    )
+}
+
+fn empty() -> Stmt {
+    Stmt::Block(Vec::new())
+}
+
+impl ExprVisitor<Expr> for Optimizer {
+    fn visit_identifier(&mut self, expr: &Expr, _id: &Token) -> Expr {
+        expr.clone()
+    }
+
+    fn visit_literal(&mut self, expr: &Expr, _lit: &Token) -> Expr {
+        expr.clone()
+    }
+
+    fn visit_logical(&mut self, _expr: &Expr, lhs: &Expr, op: &Token, rhs: &Expr) -> Expr {
+        let lhs = self.expr(lhs);
+        let rhs = self.expr(rhs);
+
+        // the operands themselves are the result
+        match constant(&lhs) {
+            Some(val) if (op.token_type == TokenType::Or) == val.is_truthy() => lhs,
+            Some(_) => rhs,
+            None => Expr::Logical(Box::new(lhs), op.clone(), Box::new(rhs)),
+        }
+    }
+
+    fn visit_conditional(
+        &mut self,
+        _expr: &Expr,
+        cond: &Expr,
+        then: &Expr,
+        else_expr: &Expr,
+    ) -> Expr {
+        let cond = self.expr(cond);
+        let then = self.expr(then);
+        let else_expr = self.expr(else_expr);
+
+        match constant(&cond) {
+            Some(val) if val.is_truthy() => then,
+            Some(_) => else_expr,
+            None => Expr::Conditional(Box::new(cond), Box::new(then), Box::new(else_expr)),
+        }
+    }
+
+    fn visit_coalesce(&mut self, _expr: &Expr, lhs: &Expr, op: &Token, rhs: &Expr) -> Expr {
+        let lhs = self.expr(lhs);
+        let rhs = self.expr(rhs);
+
+        match constant(&lhs) {
+            Some(Object::Literal(Literal::Nil)) => rhs,
+            Some(_) => lhs,
+            None => Expr::Coalesce(Box::new(lhs), op.clone(), Box::new(rhs)),
+        }
+    }
+
+    fn visit_grouping(&mut self, _expr: &Expr, group: &Expr) -> Expr {
+        match self.expr(group) {
+            lit @ Expr::Literal(_) => lit,
+            group => Expr::Grouping(Box::new(group)),
+        }
+    }
+
+    fn visit_unary(&mut self, _expr: &Expr, op: &Token, rhs: &Expr) -> Expr {
+        let rhs = self.expr(rhs);
+        let is_const = constant(&rhs).is_some();
+
+        let unary = Expr::Unary(op.clone(), Box::new(rhs));
+        if is_const {
+            self.fold(unary, op)
+        } else {
+            unary
+        }
+    }
+
+    fn visit_binary(&mut self, _expr: &Expr, lhs: &Expr, op: &Token, rhs: &Expr) -> Expr {
+        let lhs = self.expr(lhs);
+        let rhs = self.expr(rhs);
+        let is_const = constant(&lhs).is_some() && constant(&rhs).is_some();
+
+        let binary = Expr::Binary(Box::new(lhs), op.clone(), Box::new(rhs));
+        if is_const {
+            self.fold(binary, op)
+        } else {
+            binary
+        }
+    }
+
+    fn visit_assignment(&mut self, _expr: &Expr, id: &Token, val: &Expr) -> Expr {
+        Expr::Assignment(id.clone(), self.boxed(val))
+    }
+
+    fn visit_destructure(
+        &mut self,
+        _expr: &Expr,
+        targets: &[Expr],
+        bracket: &Token,
+        val: &Expr,
+    ) -> Expr {
+        Expr::Destructure(self.exprs(targets), bracket.clone(), self.boxed(val))
+    }
+
+    fn visit_update(
+        &mut self,
+        _expr: &Expr,
+        target: &Expr,
+        op: &Token,
+        val: &Expr,
+        postfix: bool,
+    ) -> Expr {
+        Expr::Update(self.boxed(target), op.clone(), self.boxed(val), postfix)
+    }
+
+    fn visit_call(
+        &mut self,
+        _expr: &Expr,
+        callee: &Expr,
+        paren: &Token,
+        args: &[Expr],
+        named: &[(Token, Expr)],
+    ) -> Expr {
+        let named = named
+            .iter()
+            .map(|(name, arg)| (name.clone(), self.expr(arg)))
+            .collect();
+
+        Expr::Call(self.boxed(callee), paren.clone(), self.exprs(args), named)
+    }
+
+    fn visit_get(&mut self, _expr: &Expr, callee: &Expr, prop: &Token, _: &InlineCache) -> Expr {
+        Expr::Get(self.boxed(callee), prop.clone(), InlineCache::default())
+    }
+
+    fn visit_optional_get(&mut self, _expr: &Expr, callee: &Expr, prop: &Token) -> Expr {
+        Expr::OptionalGet(self.boxed(callee), prop.clone())
+    }
+
+    fn visit_optional_chain(&mut self, _expr: &Expr, chain: &Expr) -> Expr {
+        Expr::OptionalChain(self.boxed(chain))
+    }
+
+    fn visit_set(
+        &mut self,
+        _expr: &Expr,
+        settee: &Expr,
+        prop: &Token,
+        val: &Expr,
+        _: &InlineCache,
+    ) -> Expr {
+        Expr::Set(
+            self.boxed(settee),
+            prop.clone(),
+            self.boxed(val),
+            InlineCache::default(),
+        )
+    }
+
+    fn visit_list(&mut self, _expr: &Expr, bracket: &Token, items: &[Expr]) -> Expr {
+        Expr::List(bracket.clone(), self.exprs(items))
+    }
+
+    fn visit_map(&mut self, _expr: &Expr, brace: &Token, entries: &[(Expr, Expr)]) -> Expr {
+        let entries = entries
+            .iter()
+            .map(|(key, val)| (self.expr(key), self.expr(val)))
+            .collect();
+
+        Expr::Map(brace.clone(), entries)
+    }
+
+    fn visit_range(&mut self, _expr: &Expr, start: &Expr, op: &Token, end: &Expr) -> Expr {
+        Expr::Range(self.boxed(start), op.clone(), self.boxed(end))
+    }
+
+    fn visit_index(&mut self, _expr: &Expr, list: &Expr, bracket: &Token, index: &Expr) -> Expr {
+        Expr::Index(self.boxed(list), bracket.clone(), self.boxed(index))
+    }
+
+    fn visit_set_index(
+        &mut self,
+        _expr: &Expr,
+        list: &Expr,
+        bracket: &Token,
+        index: &Expr,
+        val: &Expr,
+    ) -> Expr {
+        Expr::SetIndex(
+            self.boxed(list),
+            bracket.clone(),
+            self.boxed(index),
+            self.boxed(val),
+        )
+    }
+
+    fn visit_this(&mut self, expr: &Expr, _token: &Token) -> Expr {
+        expr.clone()
+    }
+
+    fn visit_super(&mut self, expr: &Expr, _keyword: &Token, _method: &Token) -> Expr {
+        expr.clone()
+    }
+}
+
+impl StmtVisitor<Stmt> for Optimizer {
+    fn visit_expr_stmt(&mut self, _stmt: &Stmt, expr: &Expr) -> Stmt {
+        Stmt::Expression(self.expr(expr))
+    }
+
+    fn visit_print(&mut self, _stmt: &Stmt, expr: &Expr) -> Stmt {
+        Stmt::Print(self.expr(expr))
+    }
+
+    fn visit_decl(&mut self, _stmt: &Stmt, id: &Token, init_expr: Option<&Expr>) -> Stmt {
+        Stmt::Declaration(id.clone(), init_expr.map(|init| self.boxed(init)))
+    }
+
+    fn visit_destructure(&mut self, _stmt: &Stmt, target: &Destructure, init: &Expr) -> Stmt {
+        Stmt::Destructure(target.clone(), self.boxed(init))
+    }
+
+    fn visit_const(&mut self, _stmt: &Stmt, id: &Token, init_expr: &Expr) -> Stmt {
+        Stmt::Const(id.clone(), self.boxed(init_expr))
+    }
+
+    fn visit_block(&mut self, _stmt: &Stmt, body: &[Stmt]) -> Stmt {
+        let mut stmts = self.stmts(body);
+
+        // nothing after a jump can run
+        if let Some(jump) = stmts.iter().position(is_jump) {
+            if !stmts[jump + 1..].iter().any(Stmt::contains_yield) {
+                stmts.truncate(jump + 1);
+            }
+        }
+
+        Stmt::Block(stmts)
+    }
+
+    fn visit_if(
+        &mut self,
+        _stmt: &Stmt,
+        cond: &Expr,
+        then: &Stmt,
+        else_stmt: Option<&Stmt>,
+    ) -> Stmt {
+        let cond = self.expr(cond);
+        let then = then.accept(self);
+        let else_stmt = else_stmt.map(|e| e.accept(self));
+
+        // the branch is run in the same scope as the if, so it can stand in for it
+        match constant(&cond) {
+            Some(val)
+                if val.is_truthy() && !else_stmt.as_ref().is_some_and(Stmt::contains_yield) =>
+            {
+                then
+            }
+            Some(val) if !val.is_truthy() && !then.contains_yield() => {
+                else_stmt.unwrap_or_else(empty)
+            }
+            _ => Stmt::If(cond, Box::new(then), else_stmt.map(Box::new)),
+        }
+    }
+
+    // Desugared for loops also end up here, so a for loop with a constant
+    // false condition is left as just its initializer
+    fn visit_while(&mut self, _stmt: &Stmt, cond: &Expr, body: &Stmt, inc: Option<&Expr>) -> Stmt {
+        let cond = self.expr(cond);
+        let body = body.accept(self);
+
+        match constant(&cond) {
+            Some(val) if !val.is_truthy() && !body.contains_yield() => empty(),
+            _ => Stmt::While(cond, Box::new(body), inc.map(|inc| self.expr(inc))),
+        }
+    }
+
+    fn visit_for_in(&mut self, _stmt: &Stmt, id: &Token, iterable: &Expr, body: &Stmt) -> Stmt {
+        Stmt::ForIn(id.clone(), self.expr(iterable), Box::new(body.accept(self)))
+    }
+
+    fn visit_break(&mut self, stmt: &Stmt, _token: &Token) -> Stmt {
+        stmt.clone()
+    }
+
+    fn visit_continue(&mut self, stmt: &Stmt, _token: &Token) -> Stmt {
+        stmt.clone()
+    }
+
+    fn visit_func(
+        &mut self,
+        _stmt: &Stmt,
+        name: &Token,
+        params: &Rc<Params>,
+        body: &Rc<Body>,
+    ) -> Stmt {
+        Stmt::Function(
+            name.clone(),
+            Rc::new(self.params(params)),
+            Body::new(body.stmt.accept(self)),
+        )
+    }
+
+    fn visit_abstract(&mut self, _stmt: &Stmt, name: &Token, params: &Params) -> Stmt {
+        Stmt::Abstract(name.clone(), self.params(params))
+    }
+
+    fn visit_return(&mut self, _stmt: &Stmt, keyword: &Token, val: Option<&Expr>) -> Stmt {
+        Stmt::Return(keyword.clone(), val.map(|val| self.boxed(val)))
+    }
+
+    fn visit_yield(&mut self, _stmt: &Stmt, keyword: &Token, val: Option<&Expr>) -> Stmt {
+        Stmt::Yield(keyword.clone(), val.map(|val| self.boxed(val)))
+    }
+
+    fn visit_class(
+        &mut self,
+        _stmt: &Stmt,
+        name: &Token,
+        parent: Option<&Expr>,
+        traits: &[Expr],
+        methods: &[Stmt],
+    ) -> Stmt {
+        Stmt::Class(
+            name.clone(),
+            parent.map(|p| Box::new(p.clone())),
+            traits.to_vec(),
+            self.stmts(methods),
+        )
+    }
+
+    fn visit_trait(&mut self, _stmt: &Stmt, name: &Token, methods: &[Stmt]) -> Stmt {
+        Stmt::Trait(name.clone(), self.stmts(methods))
+    }
+
+    fn visit_enum(&mut self, stmt: &Stmt, _name: &Token, _variants: &[Token]) -> Stmt {
+        stmt.clone()
+    }
+
+    fn visit_match(
+        &mut self,
+        _stmt: &Stmt,
+        keyword: &Token,
+        subject: &Expr,
+        arms: &[(Pattern, Stmt)],
+    ) -> Stmt {
+        let arms = arms
+            .iter()
+            .map(|(pattern, arm)| (pattern.clone(), arm.accept(self)))
+            .collect();
+
+        Stmt::Match(keyword.clone(), self.expr(subject), arms)
+    }
+}
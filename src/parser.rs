@@ -1,27 +1,31 @@
-use std::iter::Peekable;
+use std::{iter::Peekable, rc::Rc};
 
 use crate::{
+    enums,
     error::{Result, RloxError},
     expr::Expr,
-    scanner::Scanner,
-    stmt::Stmt,
+    pattern::Pattern,
+    shape::InlineCache,
+    stmt::{Body, Destructure, Params, Stmt},
+    symbol::Symbol,
     tokens::{Literal, Token, TokenType},
 };
 use TokenType::*;
 
-pub(crate) struct Parser<'a> {
-    src: Peekable<Scanner<'a>>,
+// Parses statements from any source of tokens, usually a Scanner
+pub(crate) struct Parser<I: Iterator<Item = Result<Token>>> {
+    src: Peekable<I>,
 }
 
-impl<'a> Parser<'a> {
-    pub fn new(src: Scanner<'a>) -> Self {
+impl<I: Iterator<Item = Result<Token>>> Parser<I> {
+    pub fn new(src: I) -> Self {
         Self {
             src: src.peekable(),
         }
     }
 }
 
-impl<'a> Iterator for Parser<'a> {
+impl<I: Iterator<Item = Result<Token>>> Iterator for Parser<I> {
     type Item = Result<Stmt>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -39,10 +43,11 @@ impl<'a> Iterator for Parser<'a> {
 }
 
 // Statement related methods
-impl<'a> Parser<'a> {
+impl<I: Iterator<Item = Result<Token>>> Parser<I> {
     fn statement(&mut self) -> Result<Stmt> {
         let token = self.check_advance(&[
-            Print, Var, LBrace, If, While, For, Break, Fun, Return, Class,
+            Print, Var, Const, Let, LBrace, If, While, For, Break, Continue, Fun, Return, Yield,
+            Class, Trait, Enum, Match,
         ]);
         if token.is_none() {
             return self.expr_statement();
@@ -53,14 +58,20 @@ impl<'a> Parser<'a> {
         match token.token_type {
             Print => self.print_statement(),
             Var => self.decl_statement(),
+            Const | Let => self.const_statement(),
             LBrace => self.block_statement(),
             If => self.if_statement(),
             While => self.while_statement(),
             For => self.for_statement(),
             Break => self.break_statement(token),
+            Continue => self.continue_statement(token),
             Fun => self.function(),
             Return => self.return_statement(token),
+            Yield => self.yield_statement(token),
             Class => self.class_decl(),
+            Trait => self.trait_decl(),
+            Enum => self.enum_decl(),
+            Match => self.match_statement(token),
             _ => unreachable!(),
         }
     }
@@ -78,7 +89,79 @@ impl<'a> Parser<'a> {
     }
 
     fn decl_statement(&mut self) -> Result<Stmt> {
+        let target = match self.check_advance(&[LBracket, LBrace]) {
+            Some(token) => self.destructure(token?)?,
+            None => {
+                let id = self.must_advance(&[Ident])?;
+                return self.finish_decl(id);
+            }
+        };
+
+        self.must_advance(&[Equal])?;
+        let init_expr = self.expression()?;
+        self.must_advance(&[SemiColon])?;
+
+        Ok(Stmt::Destructure(target, Box::new(init_expr)))
+    }
+
+    // [a, b, ...rest] or {x, y}, after the opening bracket
+    fn destructure(&mut self, open: Token) -> Result<Destructure> {
+        let close = if open.token_type == LBracket {
+            RBracket
+        } else {
+            RBrace
+        };
+
+        let mut names: Vec<Token> = Vec::new();
+        let mut rest = None;
+        while !self.check(&[close]) {
+            let is_rest = open.token_type == LBracket && self.check_advance(&[Ellipsis]).is_some();
+            let name = self.must_advance(&[Ident])?;
+
+            // caught here rather than by the resolver so that globals are checked too
+            if names.iter().any(|n| n.lexeme == name.lexeme) {
+                return Err(RloxError::Parse(
+                    name.line,
+                    "Duplicate name in destructuring".to_string(),
+                    name.lexeme.to_string(),
+                ));
+            }
+
+            if is_rest {
+                rest = Some(name);
+                break;
+            }
+            names.push(name);
+
+            if self.check_advance(&[Comma]).is_none() {
+                break;
+            }
+        }
+        self.must_advance(&[close])?;
+
+        Ok(match open.token_type {
+            LBracket => Destructure::List(open, names, rest),
+            _ => Destructure::Fields(open, names),
+        })
+    }
+
+    fn const_statement(&mut self) -> Result<Stmt> {
         let id = self.must_advance(&[Ident])?;
+        if self.check_advance(&[Equal]).is_none() {
+            return Err(RloxError::Parse(
+                id.line,
+                "Constant must be initialized".to_string(),
+                id.lexeme.to_string(),
+            ));
+        }
+
+        let init_expr = self.expression()?;
+        self.must_advance(&[SemiColon])?;
+        Ok(Stmt::Const(id, Box::new(init_expr)))
+    }
+
+    // parses the rest of a declaration after the variable name
+    fn finish_decl(&mut self, id: Token) -> Result<Stmt> {
         if self.check_advance(&[Equal]).is_none() {
             self.must_advance(&[SemiColon])?;
             return Ok(Stmt::Declaration(id, None));
@@ -125,7 +208,7 @@ impl<'a> Parser<'a> {
 
         let body = self.statement()?;
 
-        Ok(Stmt::While(cond, Box::new(body)))
+        Ok(Stmt::While(cond, Box::new(body), None))
     }
 
     fn for_statement(&mut self) -> Result<Stmt> {
@@ -135,7 +218,14 @@ impl<'a> Parser<'a> {
             None => Some(self.expr_statement()?),
             Some(t) => match t?.token_type {
                 SemiColon => None,
-                Var => Some(self.decl_statement()?),
+                Var => {
+                    let id = self.must_advance(&[Ident])?;
+                    if self.check_advance(&[In]).is_some() {
+                        return self.for_in_statement(id);
+                    }
+
+                    Some(self.finish_decl(id)?)
+                }
                 _ => unreachable!(),
             },
         };
@@ -143,7 +233,7 @@ impl<'a> Parser<'a> {
         let cond = match self.check_advance(&[SemiColon]) {
             Some(t) => Expr::Literal(Token {
                 token_type: True,
-                lexeme: "true".to_string(),
+                lexeme: Symbol::intern("true"),
                 literal: Some(Literal::Boolean(true)),
                 ..t?
             }),
@@ -158,62 +248,130 @@ impl<'a> Parser<'a> {
             None => {
                 let expr = self.expression()?;
                 self.must_advance(&[RParen])?;
-                Some(Stmt::Expression(expr))
+                Some(expr)
             }
             Some(_) => None,
         };
 
-        let mut body = self.statement()?;
-        if inc.is_some() {
-            body = Stmt::Block(vec![body, inc.unwrap()]);
-        }
-
-        body = Stmt::While(cond, Box::new(body));
+        // the increment is kept apart from the body so that 'continue' still runs it
+        let mut body = Stmt::While(cond, Box::new(self.statement()?), inc);
 
-        if init.is_some() {
-            body = Stmt::Block(vec![init.unwrap(), body]);
+        if let Some(init) = init {
+            body = Stmt::Block(vec![init, body]);
         }
 
         Ok(body)
     }
 
+    fn for_in_statement(&mut self, id: Token) -> Result<Stmt> {
+        let iterable = self.expression()?;
+        self.must_advance(&[RParen])?;
+
+        let body = self.statement()?;
+
+        Ok(Stmt::ForIn(id, iterable, Box::new(body)))
+    }
+
     fn break_statement(&mut self, token: Token) -> Result<Stmt> {
         self.must_advance(&[SemiColon])?;
         Ok(Stmt::Break(token))
     }
 
+    fn continue_statement(&mut self, token: Token) -> Result<Stmt> {
+        self.must_advance(&[SemiColon])?;
+        Ok(Stmt::Continue(token))
+    }
+
     fn function(&mut self) -> Result<Stmt> {
-        use crate::stmt::FUNCTION_MAX_ARGS;
         let name = self.must_advance(&[Ident])?;
+        self.finish_function(name)
+    }
+
+    fn finish_function(&mut self, name: Token) -> Result<Stmt> {
         self.must_advance(&[LParen])?;
+        let params = self.params(&name)?;
+        self.must_advance(&[RParen])?;
+        self.must_advance(&[LBrace])?;
 
-        let mut params = Vec::new();
-        if !self.check(&[RParen]) {
-            loop {
-                if params.len() >= FUNCTION_MAX_ARGS {
+        Ok(Stmt::Function(
+            name,
+            Rc::new(params),
+            Body::new(self.block_statement()?),
+        ))
+    }
+
+    // a, b = default, ...rest
+    fn params(&mut self, name: &Token) -> Result<Params> {
+        use crate::stmt::FUNCTION_MAX_ARGS;
+
+        let mut params = Params::default();
+        if self.check(&[RParen]) {
+            return Ok(params);
+        }
+
+        loop {
+            if params.list.len() >= FUNCTION_MAX_ARGS {
+                return Err(RloxError::Parse(
+                    name.line,
+                    format!("Cannot have more than {} parameters", FUNCTION_MAX_ARGS),
+                    name.lexeme.to_string(),
+                ));
+            }
+
+            if self.check_advance(&[Ellipsis]).is_some() {
+                let rest = self.must_advance(&[Ident])?;
+                if self.check(&[Comma]) {
                     return Err(RloxError::Parse(
-                        name.line,
-                        format!("Cannot have more than {} parameters", FUNCTION_MAX_ARGS),
-                        name.lexeme,
+                        rest.line,
+                        "Rest parameter must be the last one".to_string(),
+                        rest.lexeme.to_string(),
                     ));
                 }
 
-                params.push(self.must_advance(&[Ident])?);
+                params.rest = Some(rest);
+                return Ok(params);
+            }
 
-                if self.check_advance(&[Comma]).is_none() {
-                    break;
+            let param = self.must_advance(&[Ident])?;
+            let default = match self.check_advance(&[Equal]) {
+                Some(token) => {
+                    token?;
+                    Some(self.expression()?)
+                }
+                None if params.list.iter().any(|(_, d)| d.is_some()) => {
+                    return Err(RloxError::Parse(
+                        param.line,
+                        "Parameter without a default follows one with a default".to_string(),
+                        param.lexeme.to_string(),
+                    ));
                 }
+                None => None,
+            };
+
+            if params.names().any(|p| p.lexeme == param.lexeme) {
+                return Err(RloxError::Parse(
+                    param.line,
+                    "Duplicate parameter name".to_string(),
+                    param.lexeme.to_string(),
+                ));
+            }
+            params.list.push((param, default));
+
+            if self.check_advance(&[Comma]).is_none() {
+                return Ok(params);
             }
         }
+    }
 
+    // abstract name(params); which subclasses must implement
+    fn abstract_method(&mut self) -> Result<Stmt> {
+        let name = self.must_advance(&[Ident])?;
+        self.must_advance(&[LParen])?;
+        let params = self.params(&name)?;
         self.must_advance(&[RParen])?;
-        self.must_advance(&[LBrace])?;
+        self.must_advance(&[SemiColon])?;
 
-        Ok(Stmt::Function(
-            name,
-            params,
-            Box::new(self.block_statement()?),
-        ))
+        Ok(Stmt::Abstract(name, params))
     }
 
     fn return_statement(&mut self, token: Token) -> Result<Stmt> {
@@ -227,6 +385,17 @@ impl<'a> Parser<'a> {
         Ok(Stmt::Return(token, expr))
     }
 
+    fn yield_statement(&mut self, token: Token) -> Result<Stmt> {
+        let expr = if self.check(&[SemiColon]) {
+            None
+        } else {
+            Some(Box::new(self.expression()?))
+        };
+
+        self.must_advance(&[SemiColon])?;
+        Ok(Stmt::Yield(token, expr))
+    }
+
     fn class_decl(&mut self) -> Result<Stmt> {
         let name = self.must_advance(&[Ident])?;
         let parent = if self.check_advance(&[Less]).is_some() {
@@ -235,28 +404,309 @@ impl<'a> Parser<'a> {
             None
         };
 
+        let mut traits = Vec::new();
+        if self.check_advance(&[With]).is_some() {
+            loop {
+                let t = self.must_advance(&[Ident])?;
+                if traits
+                    .iter()
+                    .any(|e| matches!(e, Expr::Identifier(ref u) if u.lexeme == t.lexeme))
+                {
+                    return Err(RloxError::Parse(
+                        t.line,
+                        "Duplicate trait".to_string(),
+                        t.lexeme.to_string(),
+                    ));
+                }
+                traits.push(Expr::Identifier(t));
+
+                if self.check_advance(&[Comma]).is_none() {
+                    break;
+                }
+            }
+        }
+
+        let methods = self.methods()?;
+        Ok(Stmt::Class(name, parent, traits, methods))
+    }
+
+    fn trait_decl(&mut self) -> Result<Stmt> {
+        let name = self.must_advance(&[Ident])?;
+        let methods = self.methods()?;
+        Ok(Stmt::Trait(name, methods))
+    }
+
+    // enum Color { Red, Green, Blue }
+    fn enum_decl(&mut self) -> Result<Stmt> {
+        let name = self.must_advance(&[Ident])?;
+        self.must_advance(&[LBrace])?;
+
+        let mut variants: Vec<Token> = Vec::new();
+        while !self.check(&[RBrace]) {
+            let variant = self.must_advance(&[Ident])?;
+            let msg = if variants.iter().any(|v| v.lexeme == variant.lexeme) {
+                Some("Duplicate enum variant")
+            } else if enums::MEMBERS.contains(&variant.lexeme.as_str()) {
+                Some("Enum variant has the name of a built-in member")
+            } else {
+                None
+            };
+
+            if let Some(msg) = msg {
+                return Err(RloxError::Parse(
+                    variant.line,
+                    msg.to_string(),
+                    variant.lexeme.to_string(),
+                ));
+            }
+            variants.push(variant);
+
+            if self.check_advance(&[Comma]).is_none() {
+                break;
+            }
+        }
+
+        self.must_advance(&[RBrace])?;
+        Ok(Stmt::Enum(name, variants))
+    }
+
+    // the body of a class or trait
+    fn methods(&mut self) -> Result<Vec<Stmt>> {
         self.must_advance(&[LBrace])?;
 
         let mut methods = Vec::new();
+        let mut method_names: Vec<Symbol> = Vec::new();
+        let mut field_names: Vec<Symbol> = Vec::new();
+        let mut abstract_names: Vec<Symbol> = Vec::new();
         while !self.check(&[RBrace]) {
-            methods.push(self.function()?);
+            if self.check_advance(&[Abstract]).is_some() {
+                let method = self.abstract_method()?;
+                if let Stmt::Abstract(ref name, _) = method {
+                    if method_names.contains(&name.lexeme) {
+                        return Err(RloxError::Parse(
+                            name.line,
+                            "Method declared both abstract and concrete".to_string(),
+                            name.lexeme.to_string(),
+                        ));
+                    }
+                    abstract_names.push(name.lexeme);
+                }
+                methods.push(method);
+                continue;
+            }
+
+            // fields are declared like variables: x = 0;
+            let name = self.must_advance(&[Ident, Private])?;
+            let is_method = self.check(&[LParen]);
+
+            // a field would hide a method of the same name
+            let msg = match (is_method, field_names.contains(&name.lexeme)) {
+                (false, true) => Some("Duplicate field"),
+                (true, true) => Some("Method has the same name as a field"),
+                (true, false) if abstract_names.contains(&name.lexeme) => {
+                    Some("Method declared both abstract and concrete")
+                }
+                (false, false) if method_names.contains(&name.lexeme) => {
+                    Some("Field has the same name as a method")
+                }
+                _ => None,
+            };
+
+            if let Some(msg) = msg {
+                return Err(RloxError::Parse(
+                    name.line,
+                    msg.to_string(),
+                    name.lexeme.to_string(),
+                ));
+            }
+
+            if is_method {
+                method_names.push(name.lexeme);
+                methods.push(self.finish_function(name)?);
+            } else {
+                field_names.push(name.lexeme);
+                methods.push(self.finish_decl(name)?);
+            }
         }
 
         self.must_advance(&[RBrace])?;
         methods.shrink_to_fit(); // why waste the extra space?
 
-        Ok(Stmt::Class(name, parent, methods))
+        Ok(methods)
+    }
+
+    fn match_statement(&mut self, keyword: Token) -> Result<Stmt> {
+        self.must_advance(&[LParen])?;
+        let subject = self.expression()?;
+        self.must_advance(&[RParen])?;
+        self.must_advance(&[LBrace])?;
+
+        let mut arms = Vec::new();
+        while !self.check(&[RBrace]) {
+            let pattern = self.pattern()?;
+            self.must_advance(&[FatArrow])?;
+            let body = self.statement()?;
+
+            // arms may optionally be separated by commas
+            if let Some(comma) = self.check_advance(&[Comma]) {
+                comma?;
+            }
+
+            arms.push((pattern, body));
+        }
+
+        self.must_advance(&[RBrace])?;
+
+        Ok(Stmt::Match(keyword, subject, arms))
+    }
+}
+
+// Pattern related methods
+impl<I: Iterator<Item = Result<Token>>> Parser<I> {
+    fn pattern(&mut self) -> Result<Pattern> {
+        let first = self.single_pattern()?;
+        if !self.check(&[Pipe]) {
+            return Ok(first);
+        }
+
+        let mut alts = vec![first];
+        while let Some(pipe) = self.check_advance(&[Pipe]) {
+            let pipe = pipe?;
+            alts.push(self.single_pattern()?);
+
+            if alts.iter().any(|alt| !alt.bindings().is_empty()) {
+                return Err(RloxError::Parse(
+                    pipe.line,
+                    "Alternative patterns cannot bind variables".to_string(),
+                    pipe.lexeme.to_string(),
+                ));
+            }
+        }
+
+        Ok(Pattern::Alternatives(alts))
+    }
+
+    fn single_pattern(&mut self) -> Result<Pattern> {
+        let token = self.must_advance(&[
+            Nil,
+            False,
+            True,
+            Number,
+            StringLiteral,
+            Minus,
+            Ident,
+            LBracket,
+        ])?;
+
+        match token.token_type {
+            Nil | False | True | Number | StringLiteral => Ok(Pattern::Literal(token)),
+            Minus => {
+                let num = self.must_advance(&[Number])?;
+                let literal = match num.literal {
+                    Some(Literal::Integer(i)) => Some(
+                        i.checked_neg()
+                            .map_or(Literal::Number(-(i as f64)), Literal::Integer),
+                    ),
+                    Some(Literal::Number(n)) => Some(Literal::Number(-n)),
+                    _ => unreachable!(),
+                };
+
+                Ok(Pattern::Literal(Token {
+                    lexeme: Symbol::intern(&format!("-{}", num.lexeme)),
+                    literal,
+                    ..num
+                }))
+            }
+            Ident if token.lexeme == "_" => Ok(Pattern::Wildcard),
+            Ident if self.check(&[Dot]) => {
+                self.must_advance(&[Dot])?;
+                let prop = self.must_advance(&[Ident])?;
+                Ok(Pattern::Value(Expr::Get(
+                    Box::new(Expr::Identifier(token)),
+                    prop,
+                    InlineCache::default(),
+                )))
+            }
+            Ident if self.check(&[LParen]) => {
+                self.must_advance(&[LParen])?;
+
+                let mut fields = Vec::new();
+                if !self.check(&[RParen]) {
+                    loop {
+                        fields.push(self.pattern()?);
+
+                        if self.check_advance(&[Comma]).is_none() {
+                            break;
+                        }
+                    }
+                }
+
+                self.must_advance(&[RParen])?;
+                Ok(Pattern::Class(Expr::Identifier(token), fields))
+            }
+            Ident => Ok(Pattern::Binding(token)),
+            LBracket => {
+                let mut items = Vec::new();
+                let mut rest = None;
+
+                if !self.check(&[RBracket]) {
+                    loop {
+                        // the rest pattern has to come last
+                        if self.check_advance(&[Ellipsis]).is_some() {
+                            let id = self.must_advance(&[Ident])?;
+                            rest = Some(Box::new(match id.lexeme.as_ref() {
+                                "_" => Pattern::Wildcard,
+                                _ => Pattern::Binding(id),
+                            }));
+                            break;
+                        }
+
+                        items.push(self.pattern()?);
+
+                        if self.check_advance(&[Comma]).is_none() {
+                            break;
+                        }
+                    }
+                }
+
+                self.must_advance(&[RBracket])?;
+                Ok(Pattern::List(items, rest))
+            }
+            _ => unreachable!(),
+        }
     }
 }
 
 // Expression related methods
-impl<'a> Parser<'a> {
+//
+// Precedence, from loosest to tightest binding:
+//   assignment    = += -= *= /= %=   right
+//   conditional   ?:                 right
+//   coalesce      ??                 left
+//   logical_or    or                 left
+//   logical_and   and                left
+//   equality      == !=              left
+//   comparison    < <= > >=          left
+//   range         ..                 none
+//   bit_or        |                  left
+//   bit_xor       ^                  left
+//   bit_and       &                  left
+//   shift         << >>              left
+//   term          + -                left
+//   factor        * / %              left
+//   unary         ! - ~ ++ --        right
+//   power         **                 right
+//   postfix       ++ --              left
+//   call          () . ?. []         left
+//
+// '**' binds tighter than unary minus, so -2 ** 2 is -(2 ** 2).
+impl<I: Iterator<Item = Result<Token>>> Parser<I> {
     fn expression(&mut self) -> Result<Expr> {
         self.assignment()
     }
 
     fn assignment(&mut self) -> Result<Expr> {
-        let expr = self.logical_or()?;
+        let expr = self.conditional()?;
 
         if let Some(res) = self.check_advance(&[Equal]) {
             let equals = res?;
@@ -265,13 +715,113 @@ impl<'a> Parser<'a> {
                 Expr::Identifier(token) => {
                     return Ok(Expr::Assignment(token, Box::new(self.assignment()?)))
                 }
-                Expr::Get(settee, prop) => {
-                    return Ok(Expr::Set(settee, prop, Box::new(self.assignment()?)));
+                Expr::Get(settee, prop, cache) => {
+                    return Ok(Expr::Set(settee, prop, Box::new(self.assignment()?), cache));
+                }
+                Expr::Index(list, bracket, index) => {
+                    return Ok(Expr::SetIndex(
+                        list,
+                        bracket,
+                        index,
+                        Box::new(self.assignment()?),
+                    ));
+                }
+                Expr::List(bracket, targets) => {
+                    if let Some(target) = targets.iter().find(|t| {
+                        !matches!(
+                            t,
+                            Expr::Identifier(_) | Expr::Get(..) | Expr::Index(_, _, _)
+                        )
+                    }) {
+                        return Err(RloxError::Parse(
+                            bracket.line,
+                            "Invalid destructuring target".to_string(),
+                            format!("{}", target),
+                        ));
+                    }
+
+                    return Ok(Expr::Destructure(
+                        targets,
+                        bracket,
+                        Box::new(self.assignment()?),
+                    ));
                 }
-                _ => return Err(Parser::unexpected(&equals)),
+                _ => return Err(Self::unexpected(&equals)),
             }
         }
 
+        if let Some(res) =
+            self.check_advance(&[PlusEqual, MinusEqual, StarEqual, SlashEqual, PercentEqual])
+        {
+            let op = res?;
+            let val = self.assignment()?;
+
+            return Self::update(expr, op, val, false);
+        }
+
+        Ok(expr)
+    }
+
+    // Builds the Expr for compound assignment and increment/decrement.
+    // The operator token is turned into its binary counterpart.
+    fn update(target: Expr, op: Token, val: Expr, postfix: bool) -> Result<Expr> {
+        match target {
+            Expr::Identifier(_) | Expr::Get(..) | Expr::Index(_, _, _) => (),
+            _ => return Err(Self::unexpected(&op)),
+        }
+
+        let (token_type, lexeme) = match op.token_type {
+            PlusEqual | PlusPlus => (Plus, "+"),
+            MinusEqual | MinusMinus => (Minus, "-"),
+            StarEqual => (Star, "*"),
+            SlashEqual => (Slash, "/"),
+            PercentEqual => (Percent, "%"),
+            _ => unreachable!(),
+        };
+        let op = Token {
+            token_type,
+            lexeme: Symbol::intern(lexeme),
+            ..op
+        };
+
+        Ok(Expr::Update(Box::new(target), op, Box::new(val), postfix))
+    }
+
+    // the operand of ++ and --
+    fn one(op: &Token) -> Expr {
+        Expr::Literal(Token {
+            token_type: Number,
+            lexeme: Symbol::intern("1"),
+            literal: Some(Literal::Integer(1)),
+            ..op.clone()
+        })
+    }
+
+    fn conditional(&mut self) -> Result<Expr> {
+        let expr = self.coalesce()?;
+
+        if self.check_advance(&[Question]).is_some() {
+            let then = self.expression()?;
+            self.must_advance(&[Colon])?;
+            let else_expr = self.conditional()?;
+
+            return Ok(Expr::Conditional(
+                Box::new(expr),
+                Box::new(then),
+                Box::new(else_expr),
+            ));
+        }
+
+        Ok(expr)
+    }
+
+    fn coalesce(&mut self) -> Result<Expr> {
+        let mut expr = self.logical_or()?;
+
+        while let Some(op) = self.check_advance(&[QuestionQuestion]) {
+            expr = Expr::Coalesce(Box::new(expr), op?, Box::new(self.logical_or()?));
+        }
+
         Ok(expr)
     }
 
@@ -306,9 +856,59 @@ impl<'a> Parser<'a> {
     }
 
     fn comparison(&mut self) -> Result<Expr> {
-        let mut expr = self.term()?;
+        let mut expr = self.range()?;
 
         while let Some(op) = self.check_advance(&[Greater, GreaterEqual, Less, LessEqual]) {
+            expr = Expr::Binary(Box::new(expr), op?, Box::new(self.range()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn range(&mut self) -> Result<Expr> {
+        let expr = self.bit_or()?;
+
+        if let Some(op) = self.check_advance(&[DotDot]) {
+            return Ok(Expr::Range(Box::new(expr), op?, Box::new(self.bit_or()?)));
+        }
+
+        Ok(expr)
+    }
+
+    fn bit_or(&mut self) -> Result<Expr> {
+        let mut expr = self.bit_xor()?;
+
+        while let Some(op) = self.check_advance(&[Pipe]) {
+            expr = Expr::Binary(Box::new(expr), op?, Box::new(self.bit_xor()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr> {
+        let mut expr = self.bit_and()?;
+
+        while let Some(op) = self.check_advance(&[Caret]) {
+            expr = Expr::Binary(Box::new(expr), op?, Box::new(self.bit_and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn bit_and(&mut self) -> Result<Expr> {
+        let mut expr = self.shift()?;
+
+        while let Some(op) = self.check_advance(&[Amp]) {
+            expr = Expr::Binary(Box::new(expr), op?, Box::new(self.shift()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn shift(&mut self) -> Result<Expr> {
+        let mut expr = self.term()?;
+
+        while let Some(op) = self.check_advance(&[LessLess, GreaterGreater]) {
             expr = Expr::Binary(Box::new(expr), op?, Box::new(self.term()?));
         }
 
@@ -328,7 +928,7 @@ impl<'a> Parser<'a> {
     fn factor(&mut self) -> Result<Expr> {
         let mut expr = self.unary()?;
 
-        while let Some(op) = self.check_advance(&[Slash, Star]) {
+        while let Some(op) = self.check_advance(&[Slash, Star, Percent]) {
             expr = Expr::Binary(Box::new(expr), op?, Box::new(self.unary()?));
         }
 
@@ -336,43 +936,119 @@ impl<'a> Parser<'a> {
     }
 
     fn unary(&mut self) -> Result<Expr> {
-        if let Some(op) = self.check_advance(&[Bang, Minus]) {
+        if let Some(op) = self.check_advance(&[Bang, Minus, Tilde]) {
             return Ok(Expr::Unary(op?, Box::new(self.unary()?)));
         }
 
-        self.call()
+        if let Some(op) = self.check_advance(&[PlusPlus, MinusMinus]) {
+            let op = op?;
+            let one = Self::one(&op);
+            return Self::update(self.unary()?, op, one, false);
+        }
+
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Expr> {
+        let expr = self.postfix()?;
+
+        // the exponent may itself be negated or another power: 2 ** -1, 2 ** 3 ** 2
+        if let Some(op) = self.check_advance(&[StarStar]) {
+            return Ok(Expr::Binary(Box::new(expr), op?, Box::new(self.unary()?)));
+        }
+
+        Ok(expr)
+    }
+
+    fn postfix(&mut self) -> Result<Expr> {
+        let expr = self.call()?;
+
+        if let Some(op) = self.check_advance(&[PlusPlus, MinusMinus]) {
+            let op = op?;
+            let one = Self::one(&op);
+            return Self::update(expr, op, one, true);
+        }
+
+        Ok(expr)
     }
 
     fn call(&mut self) -> Result<Expr> {
         let mut expr = self.primary()?;
+        let mut optional = false;
 
         loop {
-            expr = match self.check_advance(&[LParen, Dot]) {
+            expr = match self.check_advance(&[LParen, Dot, QuestionDot, LBracket]) {
                 Some(Err(e)) => return Err(e),
-                Some(Ok(ref token)) => match token.token_type {
+                Some(Ok(token)) => match token.token_type {
                     LParen => self.finish_call(expr)?,
-                    Dot => Expr::Get(Box::new(expr), self.must_advance(&[Ident])?),
+                    LBracket => {
+                        let index = self.expression()?;
+                        self.must_advance(&[RBracket])?;
+                        Expr::Index(Box::new(expr), token, Box::new(index))
+                    }
+                    Dot => Expr::Get(
+                        Box::new(expr),
+                        self.must_advance(&[Ident, Private])?,
+                        InlineCache::default(),
+                    ),
+                    QuestionDot => {
+                        optional = true;
+                        let prop = self.must_advance(&[Ident, Private])?;
+                        Expr::OptionalGet(Box::new(expr), prop)
+                    }
                     _ => unreachable!(),
                 },
                 None => break,
             };
         }
 
+        // a nil before any ?. skips the rest of the chain
+        if optional {
+            expr = Expr::OptionalChain(Box::new(expr));
+        }
+
         Ok(expr)
     }
 
     fn finish_call(&mut self, callee: Expr) -> Result<Expr> {
         let mut args = Vec::new();
+        let mut named: Vec<(Token, Expr)> = Vec::new();
         if !self.check(&[RParen]) {
             loop {
-                if args.len() >= crate::stmt::FUNCTION_MAX_ARGS {
+                if args.len() + named.len() >= crate::stmt::FUNCTION_MAX_ARGS {
                     return Err(RloxError::Parse(
                         0,
                         "Can't have more than 255 arguments".to_string(),
                         "".to_string(),
                     ));
                 }
-                args.push(self.expression()?);
+
+                let arg = self.expression()?;
+
+                // a lone identifier followed by ':' names the parameter
+                match (arg, self.check_advance(&[Colon])) {
+                    (Expr::Identifier(name), Some(colon)) => {
+                        colon?;
+                        if named.iter().any(|(n, _)| n.lexeme == name.lexeme) {
+                            return Err(RloxError::Parse(
+                                name.line,
+                                "Argument given more than once".to_string(),
+                                name.lexeme.to_string(),
+                            ));
+                        }
+                        named.push((name, self.expression()?));
+                    }
+                    (_, Some(colon)) => return Err(Self::unexpected(&colon?)),
+                    (_, None) if !named.is_empty() => {
+                        let (ref name, _) = named[named.len() - 1];
+                        return Err(RloxError::Parse(
+                            name.line,
+                            "Positional argument follows named argument".to_string(),
+                            name.lexeme.to_string(),
+                        ));
+                    }
+                    (arg, None) => args.push(arg),
+                }
 
                 match self.check_advance(&[Comma]) {
                     Some(token) => token?,
@@ -385,6 +1061,7 @@ impl<'a> Parser<'a> {
             Box::new(callee),
             self.must_advance(&[RParen])?,
             args,
+            named,
         ))
     }
 
@@ -396,10 +1073,48 @@ impl<'a> Parser<'a> {
                 Ident => Ok(Expr::Identifier(token)),
                 This => Ok(Expr::This(token)),
                 Nil | False | True | Number | StringLiteral => Ok(Expr::Literal(token)),
-                _ => Err(Parser::unexpected(&token)),
+                _ => Err(Self::unexpected(&token)),
             };
         }
 
+        if let Some(Ok(bracket)) = self.check_advance(&[LBracket]) {
+            let mut items = Vec::new();
+            if !self.check(&[RBracket]) {
+                loop {
+                    items.push(self.expression()?);
+
+                    if self.check_advance(&[Comma]).is_none() {
+                        break;
+                    }
+                }
+            }
+            self.must_advance(&[RBracket])?;
+
+            return Ok(Expr::List(bracket, items));
+        }
+
+        if let Some(Ok(brace)) = self.check_advance(&[LBrace]) {
+            let mut entries = Vec::new();
+            if !self.check(&[RBrace]) {
+                loop {
+                    let key = self.expression()?;
+                    self.must_advance(&[Colon])?;
+                    entries.push((key, self.expression()?));
+
+                    if self.check_advance(&[Comma]).is_none() {
+                        break;
+                    }
+                }
+            }
+            self.must_advance(&[RBrace])?;
+
+            return Ok(Expr::Map(brace, entries));
+        }
+
+        if let Some(Ok(token)) = self.check_advance(&[Interpolation]) {
+            return self.interpolation(token);
+        }
+
         if let Some(Ok(token)) = self.check_advance(&[Super]) {
             self.must_advance(&[Dot])?;
             let method = self.must_advance(&[Ident])?;
@@ -416,13 +1131,45 @@ impl<'a> Parser<'a> {
 
         Err(self.peek_err())
     }
+
+    // "a ${b} c" is scanned as Interpolation("a ") b StringLiteral(" c")
+    // and becomes the concatenation ("a " + b) + " c"
+    fn interpolation(&mut self, head: Token) -> Result<Expr> {
+        let plus = Token {
+            token_type: Plus,
+            lexeme: Symbol::intern("+"),
+            literal: None,
+            ..head.clone()
+        };
+        let mut expr = Expr::Literal(Token {
+            token_type: StringLiteral,
+            ..head
+        });
+
+        loop {
+            let part = self.expression()?;
+            expr = Expr::Binary(Box::new(expr), plus.clone(), Box::new(part));
+
+            let tail = self.must_advance(&[Interpolation, StringLiteral])?;
+            let done = tail.token_type == StringLiteral;
+            let tail = Expr::Literal(Token {
+                token_type: StringLiteral,
+                ..tail
+            });
+            expr = Expr::Binary(Box::new(expr), plus.clone(), Box::new(tail));
+
+            if done {
+                return Ok(expr);
+            }
+        }
+    }
 }
 
 // helper token related methods
-impl<'a> Parser<'a> {
+impl<I: Iterator<Item = Result<Token>>> Parser<I> {
     fn check(&mut self, types: &[TokenType]) -> bool {
         match self.src.peek() {
-            Some(&Ok(ref t)) => t.in_types(types),
+            Some(Ok(t)) => t.in_types(types),
             _ => false,
         }
     }
@@ -445,7 +1192,7 @@ impl<'a> Parser<'a> {
 
     fn peek_err(&mut self) -> RloxError {
         match self.src.peek() {
-            Some(Ok(ref token)) => Parser::unexpected(token),
+            Some(Ok(ref token)) => Self::unexpected(token),
             None => RloxError::Parse(0, "".to_string(), "Unexpectef EOF".to_string()),
 
             _ => self.src.next().unwrap().unwrap_err(),
@@ -455,7 +1202,7 @@ impl<'a> Parser<'a> {
     fn unexpected(token: &Token) -> RloxError {
         let lex = match token.token_type {
             TokenType::Eof => "EOF".to_string(),
-            _ => token.lexeme.clone(),
+            _ => token.lexeme.to_string(),
         };
         RloxError::Parse(token.line, "Unexpected Token".to_string(), lex)
     }
@@ -477,7 +1224,10 @@ impl<'a> Parser<'a> {
 
             if let Some(Ok(token)) = token {
                 if token.token_type == SemiColon
-                    && self.check(&[Class, Fun, Var, For, If, While, Print, Return])
+                    && self.check(&[
+                        Class, Trait, Enum, Fun, Var, Const, Let, For, If, While, Print, Return,
+                        Yield, Match,
+                    ])
                 {
                     return;
                 }
@@ -486,12 +1236,10 @@ impl<'a> Parser<'a> {
     }
 }
 
-pub(crate) trait StmtIterator<'a> {
-    fn statements(self) -> Parser<'a>;
-}
-
-impl<'a> StmtIterator<'a> for Scanner<'a> {
-    fn statements(self) -> Parser<'a> {
+pub(crate) trait StmtIterator: Iterator<Item = Result<Token>> + Sized {
+    fn statements(self) -> Parser<Self> {
         Parser::new(self)
     }
 }
+
+impl<I: Iterator<Item = Result<Token>>> StmtIterator for I {}
@@ -0,0 +1,80 @@
+use crate::{expr::Expr, tokens::Token};
+
+// Patterns are only found in the arms of a match statement
+#[derive(Debug, Clone)]
+pub(crate) enum Pattern {
+    // _
+    Wildcard,
+    // 1, -2.5, "x", true, nil
+    Literal(Token),
+    // binds the matched value to a fresh variable
+    Binding(Token),
+    // Point(x, y); the sub-patterns match the fields named by
+    // the parameters of the class's init method, in order
+    Class(Expr, Vec<Pattern>),
+    // [head, ...rest]
+    List(Vec<Pattern>, Option<Box<Pattern>>),
+    // Color.Red; matches values equal to the one named
+    Value(Expr),
+    // 1 | 2; alternatives cannot bind variables
+    Alternatives(Vec<Pattern>),
+}
+
+impl Pattern {
+    // All the variables bound by this pattern, in order
+    pub(crate) fn bindings(&self) -> Vec<&Token> {
+        let mut ids = Vec::new();
+        self.collect_bindings(&mut ids);
+        ids
+    }
+
+    fn collect_bindings<'a>(&'a self, ids: &mut Vec<&'a Token>) {
+        match self {
+            Pattern::Binding(ref id) => ids.push(id),
+            Pattern::Class(_, ref fields) => fields.iter().for_each(|p| p.collect_bindings(ids)),
+            Pattern::List(ref items, ref rest) => {
+                items.iter().for_each(|p| p.collect_bindings(ids));
+                if let Some(rest) = rest {
+                    rest.collect_bindings(ids);
+                }
+            }
+            Pattern::Alternatives(ref alts) => alts.iter().for_each(|p| p.collect_bindings(ids)),
+            Pattern::Wildcard | Pattern::Literal(_) | Pattern::Value(_) => (),
+        }
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |patterns: &[Pattern], sep| {
+            patterns
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+                .join(sep)
+        };
+
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Literal(ref lit) => write!(f, "{}", lit.lexeme),
+            Pattern::Binding(ref id) => write!(f, "{}", id.lexeme),
+            Pattern::Class(Expr::Identifier(ref class), ref fields) => {
+                write!(f, "{}({})", class.lexeme, join(fields, ", "))
+            }
+            Pattern::Class(ref class, ref fields) => write!(f, "{}({})", class, join(fields, ", ")),
+            Pattern::List(ref items, None) => write!(f, "[{}]", join(items, ", ")),
+            Pattern::List(ref items, Some(ref rest)) if items.is_empty() => {
+                write!(f, "[...{}]", rest)
+            }
+            Pattern::List(ref items, Some(ref rest)) => {
+                write!(f, "[{}, ...{}]", join(items, ", "), rest)
+            }
+            Pattern::Value(Expr::Get(ref callee, ref prop, _)) => match callee.as_ref() {
+                Expr::Identifier(ref id) => write!(f, "{}.{}", id.lexeme, prop.lexeme),
+                _ => write!(f, "{}.{}", callee, prop.lexeme),
+            },
+            Pattern::Value(ref val) => write!(f, "{}", val),
+            Pattern::Alternatives(ref alts) => write!(f, "{}", join(alts, " | ")),
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::{
     class::ClassType,
@@ -6,16 +9,23 @@ use crate::{
     expr::{Expr, Visitor as ExprVisitor},
     functions::FunctionType,
     interpreter::Interpreter,
-    stmt::{Stmt, Visitor as StmtVisitor},
-    tokens::Token,
+    pattern::Pattern,
+    shape::InlineCache,
+    stmt::{Body, Destructure, Params, Stmt, Visitor as StmtVisitor},
+    symbol::Symbol,
+    tokens::{Token, TokenType},
 };
 
 pub(crate) struct Resolver<'a> {
     interpreter: &'a mut Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<HashMap<Symbol, bool>>,
+    // the constants declared in each scope; globals are checked at runtime instead
+    constants: Vec<HashSet<Symbol>>,
     current_func: FunctionType,
     current_class: ClassType,
     in_loop: bool,
+    // generators cannot hand their calls back to a caller's loop
+    in_generator: bool,
 }
 
 impl<'a> Resolver<'a> {
@@ -23,9 +33,11 @@ impl<'a> Resolver<'a> {
         Self {
             interpreter: i,
             scopes: Vec::new(),
+            constants: Vec::new(),
             current_func: FunctionType::None,
             current_class: ClassType::None,
             in_loop: false,
+            in_generator: false,
         }
     }
 
@@ -47,13 +59,13 @@ impl<'a> ExprVisitor<Result<()>> for Resolver<'a> {
             .scopes
             .last()
             .and_then(|s| s.get(&id.lexeme))
-            .map_or(false, |is_defined| !*is_defined);
+            .is_some_and(|is_defined| !*is_defined);
 
         if own_init {
             return Err(RloxError::Parse(
                 id.line,
                 "Cannot read local variable in its own initializer".to_string(),
-                id.lexeme.clone(),
+                id.lexeme.to_string(),
             ));
         }
 
@@ -71,6 +83,23 @@ impl<'a> ExprVisitor<Result<()>> for Resolver<'a> {
         rhs.accept(self)
     }
 
+    fn visit_conditional(
+        &mut self,
+        _expr: &Expr,
+        cond: &Expr,
+        then: &Expr,
+        else_expr: &Expr,
+    ) -> Result<()> {
+        cond.accept(self)?;
+        then.accept(self)?;
+        else_expr.accept(self)
+    }
+
+    fn visit_coalesce(&mut self, _expr: &Expr, lhs: &Expr, _op: &Token, rhs: &Expr) -> Result<()> {
+        lhs.accept(self)?;
+        rhs.accept(self)
+    }
+
     fn visit_grouping(&mut self, _expr: &Expr, group: &Expr) -> Result<()> {
         group.accept(self)
     }
@@ -85,17 +114,65 @@ impl<'a> ExprVisitor<Result<()>> for Resolver<'a> {
     }
 
     fn visit_assignment(&mut self, expr: &Expr, id: &Token, val: &Expr) -> Result<()> {
+        self.assignable(id)?;
         val.accept(self)?;
         self.resolve_local(id, expr);
         Ok(())
     }
 
+    fn visit_destructure(
+        &mut self,
+        _expr: &Expr,
+        targets: &[Expr],
+        _bracket: &Token,
+        val: &Expr,
+    ) -> Result<()> {
+        val.accept(self)?;
+
+        for target in targets {
+            match target {
+                Expr::Identifier(ref id) => {
+                    self.assignable(id)?;
+                    self.resolve_local(id, target);
+                }
+                Expr::Get(ref settee, ref prop, _) => {
+                    self.private_access(settee, prop)?;
+                    settee.accept(self)?;
+                }
+                Expr::Index(ref list, _, ref index) => {
+                    list.accept(self)?;
+                    index.accept(self)?;
+                }
+                _ => unreachable!("the parser only allows assignable targets"),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_update(
+        &mut self,
+        _expr: &Expr,
+        target: &Expr,
+        _op: &Token,
+        val: &Expr,
+        _postfix: bool,
+    ) -> Result<()> {
+        if let Expr::Identifier(ref id) = target {
+            self.assignable(id)?;
+        }
+
+        val.accept(self)?;
+        target.accept(self)
+    }
+
     fn visit_call(
         &mut self,
         _expr: &Expr,
         callee: &Expr,
         _paren: &Token,
         args: &[Expr],
+        named: &[(Token, Expr)],
     ) -> Result<()> {
         callee.accept(self)?;
 
@@ -103,24 +180,98 @@ impl<'a> ExprVisitor<Result<()>> for Resolver<'a> {
             arg.accept(self)?;
         }
 
+        for (_, arg) in named {
+            arg.accept(self)?;
+        }
+
         Ok(())
     }
 
-    fn visit_get(&mut self, _expr: &Expr, callee: &Expr, _prop: &Token) -> Result<()> {
+    fn visit_get(
+        &mut self,
+        _expr: &Expr,
+        callee: &Expr,
+        prop: &Token,
+        _: &InlineCache,
+    ) -> Result<()> {
+        self.private_access(callee, prop)?;
+        callee.accept(self)
+    }
+
+    fn visit_optional_get(&mut self, _expr: &Expr, callee: &Expr, prop: &Token) -> Result<()> {
+        self.private_access(callee, prop)?;
         callee.accept(self)
     }
 
-    fn visit_set(&mut self, _expr: &Expr, settee: &Expr, _prop: &Token, val: &Expr) -> Result<()> {
+    fn visit_optional_chain(&mut self, _expr: &Expr, chain: &Expr) -> Result<()> {
+        chain.accept(self)
+    }
+
+    fn visit_set(
+        &mut self,
+        _expr: &Expr,
+        settee: &Expr,
+        prop: &Token,
+        val: &Expr,
+        _: &InlineCache,
+    ) -> Result<()> {
+        self.private_access(settee, prop)?;
         val.accept(self)?;
         settee.accept(self)
     }
 
+    fn visit_list(&mut self, _expr: &Expr, _bracket: &Token, items: &[Expr]) -> Result<()> {
+        for item in items {
+            item.accept(self)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_map(&mut self, _expr: &Expr, _brace: &Token, entries: &[(Expr, Expr)]) -> Result<()> {
+        for (key, val) in entries {
+            key.accept(self)?;
+            val.accept(self)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_range(&mut self, _expr: &Expr, start: &Expr, _op: &Token, end: &Expr) -> Result<()> {
+        start.accept(self)?;
+        end.accept(self)
+    }
+
+    fn visit_index(
+        &mut self,
+        _expr: &Expr,
+        list: &Expr,
+        _bracket: &Token,
+        index: &Expr,
+    ) -> Result<()> {
+        list.accept(self)?;
+        index.accept(self)
+    }
+
+    fn visit_set_index(
+        &mut self,
+        _expr: &Expr,
+        list: &Expr,
+        _bracket: &Token,
+        index: &Expr,
+        val: &Expr,
+    ) -> Result<()> {
+        val.accept(self)?;
+        list.accept(self)?;
+        index.accept(self)
+    }
+
     fn visit_this(&mut self, expr: &Expr, token: &Token) -> Result<()> {
         if let ClassType::None = self.current_class {
             return Err(RloxError::Parse(
                 token.line,
                 "Cannot use 'this' outside of a class".to_string(),
-                token.lexeme.to_owned(),
+                token.lexeme.to_string(),
             ));
         }
 
@@ -133,12 +284,17 @@ impl<'a> ExprVisitor<Result<()>> for Resolver<'a> {
             ClassType::None => Err(RloxError::Parse(
                 keyword.line,
                 "cannot use 'super' outside of a class".to_string(),
-                keyword.lexeme.to_owned(),
+                keyword.lexeme.to_string(),
             )),
             ClassType::Class => Err(RloxError::Parse(
                 keyword.line,
                 "cannot use 'super' in a class with no superclass".to_string(),
-                keyword.lexeme.to_owned(),
+                keyword.lexeme.to_string(),
+            )),
+            ClassType::Trait => Err(RloxError::Parse(
+                keyword.line,
+                "cannot use 'super' in a trait".to_string(),
+                keyword.lexeme.to_string(),
             )),
             ClassType::SubClass => {
                 self.resolve_local(keyword, expr);
@@ -171,6 +327,33 @@ impl<'a> StmtVisitor<Result<()>> for Resolver<'a> {
         self.define(id)
     }
 
+    fn visit_destructure(&mut self, _stmt: &Stmt, target: &Destructure, init: &Expr) -> Result<()> {
+        let names = target.names();
+        for id in &names {
+            self.declare(id)?;
+        }
+
+        init.accept(self)?;
+
+        for id in names {
+            self.define(id)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_const(&mut self, _stmt: &Stmt, id: &Token, init_expr: &Expr) -> Result<()> {
+        self.declare(id)?;
+        init_expr.accept(self)?;
+        self.define(id)?;
+
+        if let Some(constants) = self.constants.last_mut() {
+            constants.insert(id.lexeme);
+        }
+
+        Ok(())
+    }
+
     fn visit_block(&mut self, _stmt: &Stmt, body: &[Stmt]) -> Result<()> {
         self.begin_scope();
 
@@ -198,12 +381,44 @@ impl<'a> StmtVisitor<Result<()>> for Resolver<'a> {
         Ok(())
     }
 
-    fn visit_while(&mut self, _stmt: &Stmt, cond: &Expr, body: &Stmt) -> Result<()> {
+    fn visit_while(
+        &mut self,
+        _stmt: &Stmt,
+        cond: &Expr,
+        body: &Stmt,
+        inc: Option<&Expr>,
+    ) -> Result<()> {
         let prev = self.in_loop;
         self.in_loop = true;
 
         cond.accept(self)?;
         body.accept(self)?;
+        if let Some(inc) = inc {
+            inc.accept(self)?;
+        }
+
+        self.in_loop = prev;
+        Ok(())
+    }
+
+    fn visit_for_in(
+        &mut self,
+        _stmt: &Stmt,
+        id: &Token,
+        iterable: &Expr,
+        body: &Stmt,
+    ) -> Result<()> {
+        iterable.accept(self)?;
+
+        let prev = self.in_loop;
+        self.in_loop = true;
+
+        // the loop variable lives in its own scope, fresh for each iteration
+        self.begin_scope();
+        self.declare(id)?;
+        self.define(id)?;
+        body.accept(self)?;
+        self.end_scope();
 
         self.in_loop = prev;
         Ok(())
@@ -216,12 +431,19 @@ impl<'a> StmtVisitor<Result<()>> for Resolver<'a> {
         Ok(())
     }
 
+    fn visit_continue(&mut self, _stmt: &Stmt, token: &Token) -> Result<()> {
+        if !self.in_loop {
+            return Err(RloxError::Continue(token.line));
+        }
+        Ok(())
+    }
+
     fn visit_func(
         &mut self,
         _stmt: &Stmt,
         name: &Token,
-        params: &[Token],
-        body: &Stmt,
+        params: &Rc<Params>,
+        body: &Rc<Body>,
     ) -> Result<()> {
         self.declare(name)?;
         self.define(name)?;
@@ -236,14 +458,44 @@ impl<'a> StmtVisitor<Result<()>> for Resolver<'a> {
                 return Err(RloxError::Parse(
                     keyword.line,
                     "cannot return from top-level code".to_string(),
-                    keyword.lexeme.to_owned(),
+                    keyword.lexeme.to_string(),
                 ))
             }
             Initializer => {
                 return Err(RloxError::Parse(
                     keyword.line,
                     "cannot return a value from an initializer".to_string(),
-                    keyword.lexeme.to_owned(),
+                    keyword.lexeme.to_string(),
+                ));
+            }
+            _ => (),
+        };
+
+        if let Some(val) = val {
+            if matches!(val, Expr::Call(..)) && !self.in_generator {
+                self.interpreter.tail_call_at(val);
+            }
+            val.accept(self)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_yield(&mut self, _stmt: &Stmt, keyword: &Token, val: Option<&Expr>) -> Result<()> {
+        use FunctionType::*;
+        match self.current_func {
+            None => {
+                return Err(RloxError::Parse(
+                    keyword.line,
+                    "cannot yield from top-level code".to_string(),
+                    keyword.lexeme.to_string(),
+                ))
+            }
+            Initializer => {
+                return Err(RloxError::Parse(
+                    keyword.line,
+                    "cannot yield from an initializer".to_string(),
+                    keyword.lexeme.to_string(),
                 ));
             }
             _ => (),
@@ -261,6 +513,7 @@ impl<'a> StmtVisitor<Result<()>> for Resolver<'a> {
         _stmt: &Stmt,
         name: &Token,
         parent: Option<&Expr>,
+        traits: &[Expr],
         methods: &[Stmt],
     ) -> Result<()> {
         let prev = self.current_class;
@@ -269,22 +522,29 @@ impl<'a> StmtVisitor<Result<()>> for Resolver<'a> {
         self.declare(name)?;
         self.define(name)?;
 
+        for t in traits {
+            t.accept(self)?;
+        }
+
         if let Some(parent) = parent {
-            self.current_class = ClassType::SubClass;
             parent.accept(self)?;
+        }
 
-            self.begin_scope();
+        // the environment holding super and the owner of private members
+        self.begin_scope();
+        if parent.is_some() {
+            self.current_class = ClassType::SubClass;
             self.scopes
                 .last_mut()
                 .unwrap()
-                .insert("super".to_string(), true);
+                .insert(Symbol::intern("super"), true);
         }
 
         self.begin_scope();
         self.scopes
             .last_mut()
             .unwrap()
-            .insert("this".to_string(), true); //FIXME: maybe use an abstraction?
+            .insert(Symbol::intern("this"), true); //FIXME: maybe use an abstraction?
 
         for method in methods {
             match method {
@@ -295,38 +555,122 @@ impl<'a> StmtVisitor<Result<()>> for Resolver<'a> {
                         FunctionType::Method
                     };
 
-                    self.resolve_function(params, body.as_ref(), func_type)?;
+                    self.resolve_function(params, body, func_type)?;
+                }
+                Stmt::Abstract(ref id, _) => self.abstract_method(id)?,
+                // initializers run with 'this' bound, like a method body
+                Stmt::Declaration(_, ref init) => {
+                    if let Some(init) = init {
+                        init.accept(self)?;
+                    }
                 }
                 _ => unreachable!(),
             }
         }
 
         self.end_scope();
-        if parent.is_some() {
-            self.end_scope();
+        self.end_scope();
+        self.current_class = prev;
+
+        Ok(())
+    }
+
+    fn visit_trait(&mut self, _stmt: &Stmt, name: &Token, methods: &[Stmt]) -> Result<()> {
+        let prev = self.current_class;
+        self.current_class = ClassType::Trait;
+
+        self.declare(name)?;
+        self.define(name)?;
+
+        // the environment holding the owner of private members
+        self.begin_scope();
+        self.begin_scope();
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(Symbol::intern("this"), true);
+
+        for method in methods {
+            match method {
+                Stmt::Function(ref id, _, _) if id.lexeme == "init" => {
+                    return Err(RloxError::Parse(
+                        id.line,
+                        "traits cannot have an initializer".to_string(),
+                        id.lexeme.to_string(),
+                    ));
+                }
+                Stmt::Function(_, ref params, ref body) => {
+                    self.resolve_function(params, body, FunctionType::Method)?;
+                }
+                Stmt::Abstract(ref id, _) => self.abstract_method(id)?,
+                Stmt::Declaration(ref id, _) => {
+                    return Err(RloxError::Parse(
+                        id.line,
+                        "traits cannot declare fields".to_string(),
+                        id.lexeme.to_string(),
+                    ));
+                }
+                _ => unreachable!(),
+            }
         }
+
+        self.end_scope();
+        self.end_scope();
         self.current_class = prev;
 
         Ok(())
     }
+
+    fn visit_enum(&mut self, _stmt: &Stmt, name: &Token, _variants: &[Token]) -> Result<()> {
+        self.declare(name)?;
+        self.define(name)
+    }
+
+    fn visit_match(
+        &mut self,
+        _stmt: &Stmt,
+        _keyword: &Token,
+        subject: &Expr,
+        arms: &[(Pattern, Stmt)],
+    ) -> Result<()> {
+        subject.accept(self)?;
+
+        // each arm gets its own scope for the variables bound by its pattern
+        for (pattern, body) in arms {
+            self.resolve_pattern(pattern)?;
+
+            self.begin_scope();
+            for id in pattern.bindings() {
+                self.declare(id)?;
+                self.define(id)?;
+            }
+
+            body.accept(self)?;
+            self.end_scope();
+        }
+
+        Ok(())
+    }
 }
 
 impl<'a> Resolver<'a> {
     fn begin_scope(&mut self) {
         self.scopes.push(HashMap::new());
+        self.constants.push(HashSet::new());
     }
 
     fn end_scope(&mut self) {
         self.scopes.pop();
+        self.constants.pop();
     }
 
     fn declare(&mut self, id: &Token) -> Result<()> {
         if let Some(scope) = self.scopes.last_mut() {
-            if scope.insert(id.lexeme.to_owned(), false).is_some() {
+            if scope.insert(id.lexeme, false).is_some() {
                 return Err(RloxError::Parse(
                     id.line,
                     "variable already defined with this name in this scope".to_string(),
-                    id.lexeme.to_owned(),
+                    id.lexeme.to_string(),
                 ));
             }
         }
@@ -336,12 +680,53 @@ impl<'a> Resolver<'a> {
 
     fn define(&mut self, id: &Token) -> Result<()> {
         if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(id.lexeme.to_owned(), true);
+            scope.insert(id.lexeme, true);
+        }
+
+        Ok(())
+    }
+
+    // #name can only follow 'this'; the interpreter checks it is the right class
+    fn private_access(&self, callee: &Expr, prop: &Token) -> Result<()> {
+        match (prop.token_type, callee) {
+            (TokenType::Private, Expr::This(_)) | (TokenType::Ident, _) => Ok(()),
+            _ => Err(RloxError::Parse(
+                prop.line,
+                "private members can only be accessed through 'this'".to_string(),
+                prop.lexeme.to_string(),
+            )),
+        }
+    }
+
+    fn abstract_method(&self, id: &Token) -> Result<()> {
+        if id.lexeme == "init" {
+            return Err(RloxError::Parse(
+                id.line,
+                "an initializer cannot be abstract".to_string(),
+                id.lexeme.to_string(),
+            ));
         }
 
         Ok(())
     }
 
+    // rejects assigning to a local constant; the innermost binding of id decides
+    fn assignable(&self, id: &Token) -> Result<()> {
+        let scope = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(&id.lexeme));
+
+        match scope {
+            Some(i) if self.constants[i].contains(&id.lexeme) => Err(RloxError::Parse(
+                id.line,
+                "cannot assign to a constant".to_string(),
+                id.lexeme.to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
     fn resolve_local(&mut self, id: &Token, expr: &Expr) {
         let len = self.scopes.len();
         for i in (0..len).rev() {
@@ -352,22 +737,65 @@ impl<'a> Resolver<'a> {
         }
     }
 
+    // class names in patterns are looked up in the enclosing scope
+    fn resolve_pattern(&mut self, pattern: &Pattern) -> Result<()> {
+        match pattern {
+            Pattern::Class(ref class, ref fields) => {
+                class.accept(self)?;
+                for field in fields {
+                    self.resolve_pattern(field)?;
+                }
+            }
+            Pattern::List(ref items, _) => {
+                for item in items {
+                    self.resolve_pattern(item)?;
+                }
+            }
+            Pattern::Alternatives(ref alts) => {
+                for alt in alts {
+                    self.resolve_pattern(alt)?;
+                }
+            }
+            Pattern::Value(ref val) => val.accept(self)?,
+            Pattern::Wildcard | Pattern::Literal(_) | Pattern::Binding(_) => (),
+        }
+
+        Ok(())
+    }
+
     fn resolve_function(
         &mut self,
-        params: &[Token],
-        body: &Stmt,
+        params: &Params,
+        body: &Body,
         func_type: FunctionType,
     ) -> Result<()> {
         let prev_type = self.current_func;
         self.current_func = func_type;
+        // loops outside the function cannot be broken out of from inside it
+        let prev_loop = self.in_loop;
+        self.in_loop = false;
+        let prev_generator = self.in_generator;
+        self.in_generator = body.generator;
         self.begin_scope();
 
-        for param in params {
+        // defaults may refer to the parameters before them
+        for (param, default) in &params.list {
+            if let Some(default) = default {
+                default.accept(self)?;
+            }
             self.declare(param)?;
+            self.define(param)?;
         }
 
-        body.accept(self)?;
+        if let Some(ref rest) = params.rest {
+            self.declare(rest)?;
+            self.define(rest)?;
+        }
+
+        body.stmt.accept(self)?;
         self.end_scope();
+        self.in_loop = prev_loop;
+        self.in_generator = prev_generator;
         self.current_func = prev_type;
 
         Ok(())
@@ -1,13 +1,29 @@
-use std::path::Path;
+use std::{
+    cell::Cell,
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    error::Result, interpreter::Interpreter, parser::StmtIterator, resolver::Resolver,
-    scanner::TokenIterator,
+    error::Result, interpreter::Interpreter, optimizer::Optimizer, parser::StmtIterator,
+    resolver::Resolver, scanner::TokenIterator, stmt::Stmt,
 };
 
-pub struct Runner;
+pub struct Runner {
+    // whether statements go through the Optimizer before running
+    pub(crate) optimize: bool,
+    // collected when running with --time
+    pub(crate) timings: Option<Timings>,
+}
 
 impl Runner {
+    pub(crate) fn new() -> Self {
+        Self {
+            optimize: true,
+            timings: None,
+        }
+    }
+
     pub(crate) fn file<P>(&mut self, f: &P) -> Result<()>
     where
         P: AsRef<Path>,
@@ -46,16 +62,121 @@ impl Runner {
     }
 
     pub(crate) fn run(&mut self, i: &mut Interpreter, src: &str) -> Result<()> {
-        for res in src.chars().tokens().statements() {
+        if self.timings.is_none() {
+            return self.run_statements(i, src.chars().tokens().statements());
+        }
+
+        let scan = Cell::new(Duration::ZERO);
+        let parse = Cell::new(Duration::ZERO);
+        let tokens = Timed::new(src.chars().tokens(), &scan);
+        let res = self.run_statements(i, Timed::new(tokens.statements(), &parse));
+
+        if let Some(ref mut timings) = self.timings {
+            timings.scan += scan.get();
+            // the parser scans tokens as it needs them
+            timings.parse += parse.get() - scan.get();
+        }
+
+        res
+    }
+
+    fn run_statements<S>(&mut self, i: &mut Interpreter, statements: S) -> Result<()>
+    where
+        S: Iterator<Item = Result<Stmt>>,
+    {
+        let mut optimizer = Optimizer::new();
+
+        for res in statements {
             match res {
                 Err(e) => eprintln!("{}", e),
                 Ok(stmt) => {
+                    // resolve first so that errors in code the optimizer drops are still reported
+                    let start = Instant::now();
                     let i = Resolver::resolve(i, &stmt)?;
-                    stmt.accept(i)?;
+                    let start = self.lap(|t| &mut t.resolve, start);
+
+                    let stmt = if self.optimize {
+                        let stmt = optimizer.optimize(&stmt);
+                        let start = self.lap(|t| &mut t.optimize, start);
+                        Resolver::resolve(i, &stmt)?;
+                        self.lap(|t| &mut t.resolve, start);
+                        stmt
+                    } else {
+                        stmt
+                    };
+
+                    let start = Instant::now();
+                    let res = stmt.accept(i);
+                    self.lap(|t| &mut t.execute, start);
+                    res?;
                 }
             }
         }
 
         Ok(())
     }
+
+    // adds the time since start to one of the timings, returning the time now
+    fn lap(&mut self, phase: fn(&mut Timings) -> &mut Duration, start: Instant) -> Instant {
+        let now = Instant::now();
+        if let Some(ref mut timings) = self.timings {
+            *phase(timings) += now - start;
+        }
+
+        now
+    }
+}
+
+// Time spent in each phase, summed over every statement run
+#[derive(Debug, Default)]
+pub(crate) struct Timings {
+    scan: Duration,
+    parse: Duration,
+    resolve: Duration,
+    optimize: Duration,
+    execute: Duration,
+}
+
+impl std::fmt::Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let phases = [
+            ("scan", self.scan),
+            ("parse", self.parse),
+            ("resolve", self.resolve),
+            ("optimize", self.optimize),
+            ("execute", self.execute),
+        ];
+
+        for (i, (phase, time)) in phases.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:<9}{:>10.3} ms", phase, time.as_secs_f64() * 1000.0)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Adds the time spent producing each item to a total
+struct Timed<'t, I> {
+    iter: I,
+    total: &'t Cell<Duration>,
+}
+
+impl<'t, I> Timed<'t, I> {
+    fn new(iter: I, total: &'t Cell<Duration>) -> Self {
+        Self { iter, total }
+    }
+}
+
+impl<I: Iterator> Iterator for Timed<'_, I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let item = self.iter.next();
+        self.total.set(self.total.get() + start.elapsed());
+        item
+    }
 }
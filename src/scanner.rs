@@ -1,7 +1,8 @@
-use std::{collections::VecDeque, str::Chars};
+use std::{collections::VecDeque, rc::Rc, str::Chars};
 
 use crate::{
     error::{Result, RloxError},
+    symbol::Symbol,
     tokens::{Literal, Token, TokenType},
 };
 
@@ -14,6 +15,8 @@ pub(crate) struct Scanner<'a> {
     line: usize,
     offset: usize,
     eof: bool,
+    // brace depth of each "${" we are currently inside
+    interpolations: Vec<usize>,
 }
 
 impl<'a> Scanner<'a> {
@@ -25,6 +28,7 @@ impl<'a> Scanner<'a> {
             line: 1,
             offset: 0,
             eof: false,
+            interpolations: Vec::new(),
         }
     }
 
@@ -110,7 +114,7 @@ impl<'a> Scanner<'a> {
     fn token(&mut self, token_type: TokenType, literal: Option<Literal>) -> Option<Result<Token>> {
         Some(Ok(Token::new(
             token_type,
-            self.lexeme.clone(),
+            Symbol::intern(&self.lexeme),
             literal,
             self.line,
             self.offset - self.lexeme.len(),
@@ -130,58 +134,234 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    // Scans the body of a string up to the closing quote or the start of an
+    // interpolation. Called after the opening quote or after the '}' that ends
+    // an interpolated expression.
     fn string(&mut self) -> Option<Result<Token>> {
-        loop {
-            let last = self.advance_until(&['\n', '"']);
+        let mut value = String::new();
+        let mut error = None;
+
+        let token_type = loop {
             match self.peek() {
-                '\0' => return self.err("Unterminated String"), // return err, implement later
-                // remove trailing slash for multiline strings
-                '"' if last == '\\' => {
-                    self.lexeme.pop();
+                '\0' => return self.err("Unterminated String"),
+                '"' => {
+                    self.advance();
+                    break TokenType::StringLiteral;
+                }
+                '$' if self.peek_next() == '{' => {
+                    self.advance();
+                    self.advance();
+                    self.interpolations.push(0);
+                    break TokenType::Interpolation;
                 }
-                '"' => break,
-                '\n' => self.line += 1,
-                _ => return self.err("Unexpected character"),
-            };
+                '\\' => {
+                    self.advance();
+                    match self.escape() {
+                        Ok(Some(ch)) => value.push(ch),
+                        Ok(None) => (),
+                        // keep going so that the rest of the string is not lexed as code
+                        Err(e) => error = error.or(Some(e)),
+                    }
+                }
+                ch => {
+                    if ch == '\n' {
+                        self.line += 1;
+                    }
+                    self.advance();
+                    value.push(ch);
+                }
+            }
+        };
 
-            self.advance();
+        if let Some(e) = error {
+            return Some(Err(e));
         }
 
+        self.token(token_type, Some(Literal::String(Rc::new(value))))
+    }
+
+    // Decodes the escape sequence following a backslash.
+    // Returns None for a line continuation, which produces no character.
+    fn escape(&mut self) -> Result<Option<char>> {
+        let ch = match self.peek() {
+            '\0' => return Ok(None), // reported as an unterminated string
+            ch => ch,
+        };
         self.advance();
 
-        // Remove the first and last char (double quotes)
-        let literal = self
-            .lexeme
-            .clone()
-            .chars()
-            .skip(1)
-            .take(self.lexeme.len() - 2)
-            .collect::<String>();
+        let decoded = match ch {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            '"' => '"',
+            '\'' => '\'',
+            '$' => '$',
+            '\n' => {
+                self.line += 1;
+                return Ok(None);
+            }
+            'u' => return self.unicode_escape().map(Some),
+            _ => {
+                return Err(RloxError::Lexical(
+                    self.line,
+                    "Invalid escape sequence".to_string(),
+                    format!("\\{}", ch),
+                ))
+            }
+        };
 
-        self.token(TokenType::StringLiteral, Some(Literal::String(literal)))
+        Ok(Some(decoded))
+    }
+
+    // \u{XXXX} with one to six hex digits
+    fn unicode_escape(&mut self) -> Result<char> {
+        let mut digits = String::new();
+        let err = |line, digits: &str| {
+            RloxError::Lexical(
+                line,
+                "Invalid unicode escape".to_string(),
+                format!("\\u{}", digits),
+            )
+        };
+
+        if !self.match_advance('{') {
+            return Err(err(self.line, &digits));
+        }
+        digits.push('{');
+
+        while self.peek().is_ascii_hexdigit() {
+            digits.push(self.advance().unwrap());
+        }
+
+        if !self.match_advance('}') {
+            return Err(err(self.line, &digits));
+        }
+        digits.push('}');
+
+        let hex = &digits[1..digits.len() - 1];
+        if hex.is_empty() || hex.len() > 6 {
+            return Err(err(self.line, &digits));
+        }
+
+        u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| err(self.line, &digits))
     }
 
     fn number(&mut self) -> Option<Result<Token>> {
-        while self.peek().is_digit(10) {
-            self.advance();
+        // the leading digit has already been consumed
+        if self.lexeme == "0" {
+            match self.peek() {
+                'x' | 'X' => return self.radix_number(16),
+                'b' | 'B' => return self.radix_number(2),
+                _ => (),
+            }
+        }
+
+        if !self.digits(10) {
+            return self.numeral_err("Digit separator must be between digits");
         }
 
+        let mut float = false;
+
         // look for fractional part
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             // consume the '.'
             self.advance();
+            float = true;
+
+            if !self.digits(10) {
+                return self.numeral_err("Digit separator must be between digits");
+            }
+        }
+
+        // look for exponent
+        if let 'e' | 'E' = self.peek() {
+            self.advance();
+            float = true;
 
-            while self.peek().is_digit(10) {
+            if let '+' | '-' = self.peek() {
                 self.advance();
             }
+
+            if !self.peek().is_ascii_digit() {
+                return self.numeral_err("Missing digits in exponent");
+            }
+
+            if !self.digits(10) {
+                return self.numeral_err("Digit separator must be between digits");
+            }
+        }
+
+        let numeral = self.lexeme.replace('_', "");
+
+        // integers too large for i64 become floats
+        if !float {
+            if let Ok(lit) = numeral.parse::<i64>() {
+                return self.token(TokenType::Number, Some(Literal::Integer(lit)));
+            }
         }
 
-        match self.lexeme.clone().parse::<f64>() {
+        match numeral.parse::<f64>() {
             Ok(lit) => self.token(TokenType::Number, Some(Literal::Number(lit))),
             Err(_) => self.err("Invalid numeral"),
         }
     }
 
+    // 0x and 0b prefixed integers
+    fn radix_number(&mut self, radix: u32) -> Option<Result<Token>> {
+        let kind = if radix == 16 { "hexadecimal" } else { "binary" };
+
+        // consume the prefix
+        self.advance();
+
+        if !self.peek().is_digit(radix) {
+            return self.numeral_err(&format!("Missing digits in {} literal", kind));
+        }
+
+        if !self.digits(radix) {
+            return self.numeral_err("Digit separator must be between digits");
+        }
+
+        if self.peek().is_ascii_alphanumeric() {
+            return self.numeral_err(&format!("Invalid digit in {} literal", kind));
+        }
+
+        match u64::from_str_radix(&self.lexeme[2..].replace('_', ""), radix) {
+            Ok(lit) if lit <= i64::MAX as u64 => {
+                self.token(TokenType::Number, Some(Literal::Integer(lit as i64)))
+            }
+            Ok(lit) => self.token(TokenType::Number, Some(Literal::Number(lit as f64))),
+            Err(_) => self.numeral_err(&format!("{} literal is too large", kind)),
+        }
+    }
+
+    // Consumes digits in the given radix with optional '_' separators.
+    // Returns false if a separator is not followed by a digit.
+    fn digits(&mut self, radix: u32) -> bool {
+        loop {
+            match self.peek() {
+                ch if ch.is_digit(radix) => (),
+                '_' if self.peek_next().is_digit(radix) => (),
+                '_' => return false,
+                _ => return true,
+            }
+            self.advance();
+        }
+    }
+
+    // Swallows the rest of a malformed numeral so it is reported as one error
+    fn numeral_err(&mut self, msg: &str) -> Option<Result<Token>> {
+        while self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
+            self.advance();
+        }
+
+        self.err(msg)
+    }
+
     fn identifer(&mut self) -> Option<Result<Token>> {
         while is_alphanumeric(self.peek()) {
             self.advance();
@@ -198,8 +378,19 @@ impl<'a> Scanner<'a> {
     }
 }
 
+impl<'a> Scanner<'a> {
+    // #name; keywords are fine as private names
+    fn private_name(&mut self) -> Option<Result<Token>> {
+        while is_alphanumeric(self.peek()) {
+            self.advance();
+        }
+
+        self.token(TokenType::Private, None)
+    }
+}
+
 fn is_alphanumeric(ch: char) -> bool {
-    ch.is_ascii_alphanumeric() || ch == '='
+    ch.is_ascii_alphanumeric() || ch == '_'
 }
 
 impl<'a> Iterator for Scanner<'a> {
@@ -223,18 +414,55 @@ impl<'a> Iterator for Scanner<'a> {
 
                 '(' => return self.token(LParen, None),
                 ')' => return self.token(RParen, None),
-                '{' => return self.token(LBrace, None),
-                '}' => return self.token(RBrace, None),
+                '{' => {
+                    if let Some(depth) = self.interpolations.last_mut() {
+                        *depth += 1;
+                    }
+                    return self.token(LBrace, None);
+                }
+                '}' => match self.interpolations.last_mut() {
+                    // closes an interpolated expression; resume the string
+                    Some(0) => {
+                        self.interpolations.pop();
+                        return self.string();
+                    }
+                    Some(depth) => {
+                        *depth -= 1;
+                        return self.token(RBrace, None);
+                    }
+                    None => return self.token(RBrace, None),
+                },
+                '[' => return self.token(LBracket, None),
+                ']' => return self.token(RBracket, None),
                 ',' => return self.token(Comma, None),
-                '.' => return self.token(Dot, None),
-                '-' => return self.token(Minus, None),
-                '+' => return self.token(Plus, None),
+                '.' if self.peek() == '.' && self.peek_next() == '.' => {
+                    self.advance();
+                    self.advance();
+                    return self.token(Ellipsis, None);
+                }
+                '.' => return self.match_token('.', (DotDot, None), (Dot, None)),
+                '-' if self.match_advance('-') => return self.token(MinusMinus, None),
+                '-' => return self.match_token('=', (MinusEqual, None), (Minus, None)),
+                '+' if self.match_advance('+') => return self.token(PlusPlus, None),
+                '+' => return self.match_token('=', (PlusEqual, None), (Plus, None)),
                 ';' => return self.token(SemiColon, None),
-                '*' => return self.token(Star, None),
+                '*' if self.match_advance('*') => return self.token(StarStar, None),
+                '*' => return self.match_token('=', (StarEqual, None), (Star, None)),
+                '%' => return self.match_token('=', (PercentEqual, None), (Percent, None)),
+                '&' => return self.token(Amp, None),
+                '|' => return self.token(Pipe, None),
+                '^' => return self.token(Caret, None),
+                '~' => return self.token(Tilde, None),
+                ':' => return self.token(Colon, None),
+                '?' if self.match_advance('?') => return self.token(QuestionQuestion, None),
+                '?' => return self.match_token('.', (QuestionDot, None), (Question, None)),
 
                 '!' => return self.match_token('=', (BangEqual, None), (Bang, None)),
+                '=' if self.match_advance('>') => return self.token(FatArrow, None),
                 '=' => return self.match_token('=', (EqualEqual, None), (Equal, None)),
+                '>' if self.match_advance('>') => return self.token(GreaterGreater, None),
                 '>' => return self.match_token('=', (GreaterEqual, None), (Greater, None)),
+                '<' if self.match_advance('<') => return self.token(LessLess, None),
                 '<' => return self.match_token('=', (LessEqual, None), (Less, None)),
 
                 '/' => match self.peek() {
@@ -243,7 +471,7 @@ impl<'a> Iterator for Scanner<'a> {
                         self.advance_until(&['\n']);
                         self.lexeme.clear();
                     }
-                    _ => return self.token(Slash, None),
+                    _ => return self.match_token('=', (SlashEqual, None), (Slash, None)),
                 },
 
                 // Ignore all whitespace
@@ -257,10 +485,12 @@ impl<'a> Iterator for Scanner<'a> {
 
                 '"' => return self.string(),
 
-                c if c.is_digit(10) => return self.number(),
+                c if c.is_ascii_digit() => return self.number(),
 
                 c if is_alphanumeric(c) => return self.identifer(),
 
+                '#' if is_alphanumeric(self.peek()) => return self.private_name(),
+
                 _ => return self.err("Unexpected Character"),
             }
         }
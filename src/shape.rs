@@ -0,0 +1,98 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{functions::Callable, symbol::Symbol};
+
+// The layout of an instance's fields. Instances which gained the same
+// fields in the same order share a shape, so a field's slot can be cached
+// against the shape instead of being looked up by name every time.
+// Every class has its own root shape, so a shape also identifies the class.
+#[derive(Debug, Default)]
+pub(crate) struct Shape {
+    slots: HashMap<Symbol, usize>,
+    // the shapes reached by adding one more field to this one
+    transitions: RefCell<HashMap<Symbol, Rc<Shape>>>,
+}
+
+impl Shape {
+    pub(crate) fn slot(&self, name: Symbol) -> Option<usize> {
+        self.slots.get(&name).copied()
+    }
+
+    // The shape with name added after the existing fields
+    pub(crate) fn with(&self, name: Symbol) -> Rc<Shape> {
+        let mut transitions = self.transitions.borrow_mut();
+        let next = transitions.entry(name).or_insert_with(|| {
+            let mut slots = self.slots.clone();
+            slots.insert(name, slots.len());
+            Rc::new(Shape {
+                slots,
+                transitions: RefCell::new(HashMap::new()),
+            })
+        });
+
+        Rc::clone(next)
+    }
+
+    // field names in the order they were added
+    pub(crate) fn names(&self) -> Vec<Symbol> {
+        let mut names: Vec<_> = self.slots.iter().collect();
+        names.sort_by_key(|(_, &slot)| slot);
+        names.into_iter().map(|(&name, _)| name).collect()
+    }
+}
+
+// What a property access found the last time it ran
+#[derive(Debug, Clone)]
+pub(crate) enum Cached {
+    // the field in this slot
+    Field(usize),
+    // a method of the instance's class; the shape rules out a field of the same name
+    Method(Callable),
+    // setting a new field, which moves the instance to this shape
+    Transition(Rc<Shape>),
+}
+
+// A cache kept in each property access of the AST. It remembers the last
+// few shapes seen there, so an access which sees instances of a handful of
+// layouts, such as a subclass and its parent, keeps hitting.
+// Class methods cannot change after the class is defined, so entries never
+// go stale; an instance of another shape simply misses and is added,
+// replacing the oldest entry once the cache is full.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct InlineCache(Rc<RefCell<Vec<Entry>>>);
+
+type Entry = (Rc<Shape>, Cached);
+
+// shapes remembered by one access
+const ENTRIES: usize = 4;
+
+impl InlineCache {
+    pub(crate) fn lookup(&self, shape: &Rc<Shape>) -> Option<Cached> {
+        self.0
+            .borrow()
+            .iter()
+            .find(|(cached, _)| Rc::ptr_eq(cached, shape))
+            .map(|(_, entry)| entry.clone())
+    }
+
+    pub(crate) fn fill(&self, shape: &Rc<Shape>, entry: Cached) {
+        let mut entries = self.0.borrow_mut();
+        if entries.len() == ENTRIES {
+            entries.remove(0);
+        }
+        entries.push((Rc::clone(shape), entry));
+    }
+}
+
+// Caches take no part in comparing or hashing expressions
+impl PartialEq for InlineCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for InlineCache {}
+
+impl std::hash::Hash for InlineCache {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+}
@@ -1,20 +1,112 @@
+use std::{collections::HashSet, rc::Rc};
+
 use crate::expr::Expr;
+use crate::pattern::Pattern;
 use crate::tokens::Token;
 
 pub const FUNCTION_MAX_ARGS: usize = 255;
 
+// The parameters of a function: fun f(a, b = 2, ...rest)
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Params {
+    // each parameter with its default value; defaults are only allowed on trailing parameters
+    pub list: Vec<(Token, Option<Expr>)>,
+    // collects extra positional arguments into a list
+    pub rest: Option<Token>,
+}
+
+impl Params {
+    pub fn names(&self) -> impl Iterator<Item = &Token> {
+        self.list.iter().map(|(name, _)| name)
+    }
+
+    // the number of parameters without a default
+    pub fn required(&self) -> usize {
+        self.list
+            .iter()
+            .filter(|(_, default)| default.is_none())
+            .count()
+    }
+}
+
+// A function body, with the statements in it which contain a yield found
+// once when it is built rather than on every call or resume
+#[derive(Debug)]
+pub(crate) struct Body {
+    pub stmt: Stmt,
+    // whether the body contains a yield, making calls return a generator
+    pub generator: bool,
+    // the addresses of the nested statements containing one
+    yields: HashSet<*const Stmt>,
+}
+
+impl Body {
+    pub fn new(stmt: Stmt) -> Rc<Self> {
+        let mut yields = HashSet::new();
+        // nested statements live on the heap, so their addresses survive the move below
+        let generator = stmt.collect_yields(&mut yields);
+
+        Rc::new(Self {
+            stmt,
+            generator,
+            yields,
+        })
+    }
+
+    // whether stmt, a statement nested in this body, contains a yield
+    pub fn yields(&self, stmt: &Stmt) -> bool {
+        self.yields.contains(&(stmt as *const Stmt))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) enum Stmt {
     Expression(Expr),
     Print(Expr),
     Declaration(Token, Option<Box<Expr>>),
+    Destructure(Destructure, Box<Expr>),
+    // const or let; the binding can't be reassigned
+    Const(Token, Box<Expr>),
     Block(Vec<Stmt>),
     If(Expr, Box<Stmt>, Option<Box<Stmt>>),
-    While(Expr, Box<Stmt>),
+    // condition, body and the increment of a desugared for loop
+    While(Expr, Box<Stmt>, Option<Expr>),
+    // for (var id in iterable) body
+    ForIn(Token, Expr, Box<Stmt>),
     Break(Token),
-    Function(Token, Vec<Token>, Box<Stmt>),
+    Continue(Token),
+    // the parameters and body are shared by every LoxFunction made from the declaration
+    Function(Token, Rc<Params>, Rc<Body>),
+    // abstract name(params); only found in class and trait bodies
+    Abstract(Token, Params),
     Return(Token, Option<Box<Expr>>),
-    Class(Token, Option<Box<Expr>>, Vec<Stmt>),
+    // turns the enclosing function into a generator
+    Yield(Token, Option<Box<Expr>>),
+    // name, superclass, traits mixed in with 'with', and methods
+    Class(Token, Option<Box<Expr>>, Vec<Expr>, Vec<Stmt>),
+    Trait(Token, Vec<Stmt>),
+    // name and variants
+    Enum(Token, Vec<Token>),
+    Match(Token, Expr, Vec<(Pattern, Stmt)>),
+}
+
+// The names bound by a destructuring declaration
+#[derive(Debug, Clone)]
+pub(crate) enum Destructure {
+    // var [a, b, ...rest] = list;
+    List(Token, Vec<Token>, Option<Token>),
+    // var {x, y} = point; reads instance fields and methods or map keys,
+    // binding nil for a name the instance or map does not have
+    Fields(Token, Vec<Token>),
+}
+
+impl Destructure {
+    pub fn names(&self) -> Vec<&Token> {
+        match self {
+            Destructure::List(_, ref items, ref rest) => items.iter().chain(rest).collect(),
+            Destructure::Fields(_, ref fields) => fields.iter().collect(),
+        }
+    }
 }
 
 // Add more functions as variants are added to Stmt
@@ -35,6 +127,14 @@ pub(crate) trait Visitor<T> {
         self.visit_stmt(_stmt)
     }
 
+    fn visit_destructure(&mut self, _stmt: &Stmt, _target: &Destructure, _init: &Expr) -> T {
+        self.visit_stmt(_stmt)
+    }
+
+    fn visit_const(&mut self, _stmt: &Stmt, _id: &Token, _init_expr: &Expr) -> T {
+        self.visit_stmt(_stmt)
+    }
+
     fn visit_block(&mut self, _stmt: &Stmt, _body: &[Stmt]) -> T {
         self.visit_stmt(_stmt)
     }
@@ -43,7 +143,11 @@ pub(crate) trait Visitor<T> {
         self.visit_stmt(_stmt)
     }
 
-    fn visit_while(&mut self, _stmt: &Stmt, _cond: &Expr, _body: &Stmt) -> T {
+    fn visit_while(&mut self, _stmt: &Stmt, _cond: &Expr, _body: &Stmt, _inc: Option<&Expr>) -> T {
+        self.visit_stmt(_stmt)
+    }
+
+    fn visit_for_in(&mut self, _stmt: &Stmt, _id: &Token, _iterable: &Expr, _body: &Stmt) -> T {
         self.visit_stmt(_stmt)
     }
 
@@ -51,7 +155,21 @@ pub(crate) trait Visitor<T> {
         self.visit_stmt(_stmt)
     }
 
-    fn visit_func(&mut self, _stmt: &Stmt, _name: &Token, _params: &[Token], _body: &Stmt) -> T {
+    fn visit_continue(&mut self, _stmt: &Stmt, _token: &Token) -> T {
+        self.visit_stmt(_stmt)
+    }
+
+    fn visit_func(
+        &mut self,
+        _stmt: &Stmt,
+        _name: &Token,
+        _params: &Rc<Params>,
+        _body: &Rc<Body>,
+    ) -> T {
+        self.visit_stmt(_stmt)
+    }
+
+    fn visit_abstract(&mut self, _stmt: &Stmt, _name: &Token, _params: &Params) -> T {
         self.visit_stmt(_stmt)
     }
 
@@ -59,15 +177,38 @@ pub(crate) trait Visitor<T> {
         self.visit_stmt(_stmt)
     }
 
+    fn visit_yield(&mut self, _stmt: &Stmt, _keyword: &Token, _val: Option<&Expr>) -> T {
+        self.visit_stmt(_stmt)
+    }
+
     fn visit_class(
         &mut self,
         _stmt: &Stmt,
         _name: &Token,
         _parent: Option<&Expr>,
+        _traits: &[Expr],
         _methods: &[Stmt],
     ) -> T {
         self.visit_stmt(_stmt)
     }
+
+    fn visit_trait(&mut self, _stmt: &Stmt, _name: &Token, _methods: &[Stmt]) -> T {
+        self.visit_stmt(_stmt)
+    }
+
+    fn visit_enum(&mut self, _stmt: &Stmt, _name: &Token, _variants: &[Token]) -> T {
+        self.visit_stmt(_stmt)
+    }
+
+    fn visit_match(
+        &mut self,
+        _stmt: &Stmt,
+        _keyword: &Token,
+        _subject: &Expr,
+        _arms: &[(Pattern, Stmt)],
+    ) -> T {
+        self.visit_stmt(_stmt)
+    }
 }
 
 impl Stmt {
@@ -80,6 +221,8 @@ impl Stmt {
             Declaration(ref id, ref init) => {
                 v.visit_decl(self, id, init.as_ref().map(|init| init.as_ref()))
             }
+            Destructure(ref target, ref init) => v.visit_destructure(self, target, init),
+            Const(ref id, ref init) => v.visit_const(self, id, init),
             Block(ref body) => v.visit_block(self, body),
             If(ref cond, ref then, ref else_stmt) => v.visit_if(
                 self,
@@ -87,15 +230,90 @@ impl Stmt {
                 then.as_ref(),
                 else_stmt.as_ref().map(|e| e.as_ref()),
             ),
-            While(ref cond, ref body) => v.visit_while(self, cond, body),
+            While(ref cond, ref body, ref inc) => v.visit_while(self, cond, body, inc.as_ref()),
+            ForIn(ref id, ref iterable, ref body) => v.visit_for_in(self, id, iterable, body),
             Break(ref token) => v.visit_break(self, token),
+            Continue(ref token) => v.visit_continue(self, token),
             Function(ref name, ref params, ref body) => v.visit_func(self, name, params, body),
+            Abstract(ref name, ref params) => v.visit_abstract(self, name, params),
             Return(ref token, ref val) => {
                 v.visit_return(self, token, val.as_ref().map(|val| val.as_ref()))
             }
-            Class(ref name, ref parent, ref methods) => {
-                v.visit_class(self, name, parent.as_ref().map(|p| p.as_ref()), methods)
+            Yield(ref token, ref val) => {
+                v.visit_yield(self, token, val.as_ref().map(|val| val.as_ref()))
+            }
+            Class(ref name, ref parent, ref traits, ref methods) => v.visit_class(
+                self,
+                name,
+                parent.as_ref().map(|p| p.as_ref()),
+                traits,
+                methods,
+            ),
+            Trait(ref name, ref methods) => v.visit_trait(self, name, methods),
+            Enum(ref name, ref variants) => v.visit_enum(self, name, variants),
+            Match(ref keyword, ref subject, ref arms) => {
+                v.visit_match(self, keyword, subject, arms)
+            }
+        }
+    }
+}
+
+impl Stmt {
+    // Whether a yield appears in this statement, not counting nested functions
+    pub fn contains_yield(&self) -> bool {
+        use Stmt::*;
+
+        match self {
+            Yield(_, _) => true,
+            Block(ref body) => body.iter().any(Stmt::contains_yield),
+            If(_, ref then, ref else_stmt) => {
+                then.contains_yield() || else_stmt.as_ref().is_some_and(|e| e.contains_yield())
+            }
+            While(_, ref body, _) | ForIn(_, _, ref body) => body.contains_yield(),
+            Match(_, _, ref arms) => arms.iter().any(|(_, arm)| arm.contains_yield()),
+            _ => false,
+        }
+    }
+
+    // Like contains_yield, also adding every nested statement which contains
+    // a yield to yields
+    fn collect_yields(&self, yields: &mut HashSet<*const Stmt>) -> bool {
+        use Stmt::*;
+
+        let mut nested = |stmt: &Stmt| {
+            let found = stmt.collect_yields(yields);
+            if found {
+                yields.insert(stmt);
+            }
+            found
+        };
+
+        // every nested statement is visited, so no short circuiting
+        match self {
+            Yield(_, _) => true,
+            Block(ref body) => body.iter().fold(false, |found, s| nested(s) | found),
+            If(_, ref then, ref else_stmt) => {
+                nested(then) | else_stmt.as_ref().is_some_and(|e| nested(e))
             }
+            While(_, ref body, _) | ForIn(_, _, ref body) => nested(body),
+            Match(_, _, ref arms) => arms
+                .iter()
+                .fold(false, |found, (_, arm)| nested(arm) | found),
+            _ => false,
+        }
+    }
+
+    // The i-th statement nested directly in this one
+    pub fn child(&self, i: usize) -> &Stmt {
+        use Stmt::*;
+
+        match (self, i) {
+            (Block(ref body), _) => &body[i],
+            (If(_, ref then, _), 0) => then,
+            (If(_, _, Some(ref else_stmt)), 1) => else_stmt,
+            (While(_, ref body, _), 0) | (ForIn(_, _, ref body), 0) => body,
+            (Match(_, _, ref arms), _) => &arms[i].1,
+            _ => unreachable!("no child statement {} in {:?}", i, self),
         }
     }
 }
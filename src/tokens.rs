@@ -1,7 +1,9 @@
-use std::{collections::HashMap, fmt};
+use std::{collections::HashMap, fmt, rc::Rc};
 
 use lazy_static::lazy_static;
 
+use crate::symbol::Symbol;
+
 lazy_static! {
     static ref RESERVED: HashMap<&'static str, TokenType> = [
         ("and", TokenType::And),
@@ -21,6 +23,16 @@ lazy_static! {
         ("var", TokenType::Var),
         ("while", TokenType::While),
         ("break", TokenType::Break),
+        ("match", TokenType::Match),
+        ("in", TokenType::In),
+        ("continue", TokenType::Continue),
+        ("yield", TokenType::Yield),
+        ("const", TokenType::Const),
+        ("let", TokenType::Let),
+        ("trait", TokenType::Trait),
+        ("with", TokenType::With),
+        ("abstract", TokenType::Abstract),
+        ("enum", TokenType::Enum),
     ]
     .iter()
     .cloned()
@@ -34,6 +46,8 @@ pub(crate) enum TokenType {
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
     Comma,
     Dot,
     Minus,
@@ -41,20 +55,46 @@ pub(crate) enum TokenType {
     SemiColon,
     Slash,
     Star,
+    Percent,
+    Amp,
+    Pipe,
+    Caret,
+    Tilde,
+    Question,
+    Colon,
 
     // One or two character
     Bang,
     Equal,
+    StarStar,
+    PlusPlus,
+    MinusMinus,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    QuestionQuestion,
+    QuestionDot,
+    FatArrow,
+    DotDot,
+    Ellipsis,
     BangEqual,
     EqualEqual,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
 
     // Literals
     Ident,
+    // #name, a private member
+    Private,
     StringLiteral,
+    // String segment followed by an interpolated expression
+    Interpolation,
     Number,
 
     // Keywords
@@ -75,6 +115,16 @@ pub(crate) enum TokenType {
     Var,
     While,
     Break,
+    Match,
+    In,
+    Continue,
+    Yield,
+    Const,
+    Let,
+    Trait,
+    With,
+    Abstract,
+    Enum,
 
     Eof,
 }
@@ -88,7 +138,7 @@ impl TokenType {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct Token {
     pub(crate) token_type: TokenType,
-    pub(crate) lexeme: String,
+    pub(crate) lexeme: Symbol,
     pub(crate) literal: Option<Literal>,
     pub(crate) line: usize,
     pub(crate) offset: usize,
@@ -97,7 +147,7 @@ pub(crate) struct Token {
 impl Token {
     pub(crate) fn new(
         token_type: TokenType,
-        lexeme: String,
+        lexeme: Symbol,
         literal: Option<Literal>,
         line: usize,
         offset: usize,
@@ -136,7 +186,7 @@ impl Default for Token {
     fn default() -> Self {
         Self {
             token_type: TokenType::Eof,
-            lexeme: "".to_string(),
+            lexeme: Symbol::default(),
             literal: None,
             line: 0,
             offset: 0,
@@ -144,12 +194,82 @@ impl Default for Token {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone)]
 pub(crate) enum Literal {
     Nil,
     Boolean(bool),
+    Integer(i64),
     Number(f64),
-    String(String),
+    String(Rc<String>),
+}
+
+impl Literal {
+    pub(crate) fn is_numeric(&self) -> bool {
+        matches!(self, Literal::Integer(_) | Literal::Number(_))
+    }
+
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Literal::Integer(i) => Some(i as f64),
+            Literal::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    // Floats are accepted as long as they hold an exact integer
+    pub(crate) fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Literal::Integer(i) => Some(i),
+            Literal::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Some(n as i64),
+            _ => None,
+        }
+    }
+}
+
+// integers and floats compare by value, so 1 == 1.0
+fn int_float_cmp(i: i64, n: f64) -> Option<std::cmp::Ordering> {
+    match (i as f64).partial_cmp(&n)? {
+        // i may have been rounded, n is integral here but may be 2^63,
+        // which doesn't fit an i64
+        std::cmp::Ordering::Equal => Some((i as i128).cmp(&(n as i128))),
+        ord => Some(ord),
+    }
+}
+
+impl PartialEq for Literal {
+    fn eq(&self, other: &Self) -> bool {
+        use std::cmp::Ordering::Equal;
+        use Literal::*;
+
+        match (self, other) {
+            (Nil, Nil) => true,
+            (Boolean(l), Boolean(r)) => l == r,
+            (Integer(l), Integer(r)) => l == r,
+            (Number(l), Number(r)) => l == r,
+            (Integer(i), Number(n)) | (Number(n), Integer(i)) => {
+                int_float_cmp(*i, *n) == Some(Equal)
+            }
+            (String(l), String(r)) => l == r,
+            _ => false,
+        }
+    }
+}
+
+impl PartialOrd for Literal {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        use Literal::*;
+
+        match (self, other) {
+            (Nil, Nil) => Some(std::cmp::Ordering::Equal),
+            (Boolean(l), Boolean(r)) => l.partial_cmp(r),
+            (Integer(l), Integer(r)) => l.partial_cmp(r),
+            (Number(l), Number(r)) => l.partial_cmp(r),
+            (Integer(i), Number(n)) => int_float_cmp(*i, *n),
+            (Number(n), Integer(i)) => int_float_cmp(*i, *n).map(|o| o.reverse()),
+            (String(l), String(r)) => l.partial_cmp(r),
+            _ => None,
+        }
+    }
 }
 
 impl Eq for Literal {}
@@ -159,6 +279,10 @@ impl std::hash::Hash for Literal {
         match self {
             Literal::Nil => 0.hash(state),
             Literal::Boolean(b) => b.hash(state),
+            // hashed like the equal float
+            Literal::Integer(i) => (*i as f64).to_bits().hash(state),
+            // -0.0 == 0.0, so both hash as 0.0
+            Literal::Number(n) if *n == 0.0 => 0.0f64.to_bits().hash(state),
             Literal::Number(n) => n.to_bits().hash(state), // hacky solution
             Literal::String(ref s) => s.hash(state),
         }
@@ -170,6 +294,7 @@ impl std::fmt::Display for Literal {
         match self {
             Literal::Nil => write!(f, "nil"),
             Literal::Boolean(b) => write!(f, "{}", b),
+            Literal::Integer(i) => write!(f, "{}", i),
             Literal::Number(n) => write!(f, "{}", n),
             Literal::String(ref s) => write!(f, "{}", s),
         }
@@ -183,6 +308,8 @@ impl std::fmt::Display for TokenType {
             TokenType::RParen => write!(f, "R_PAREN"),
             TokenType::LBrace => write!(f, "L_BRACE"),
             TokenType::RBrace => write!(f, "R_BRACE"),
+            TokenType::LBracket => write!(f, "L_BRACKET"),
+            TokenType::RBracket => write!(f, "R_BRACKET"),
             TokenType::Comma => write!(f, "COMMA"),
             TokenType::Dot => write!(f, "DOT"),
             TokenType::Minus => write!(f, "MINUS"),
@@ -190,16 +317,40 @@ impl std::fmt::Display for TokenType {
             TokenType::SemiColon => write!(f, "SEMICOLON"),
             TokenType::Slash => write!(f, "SLASH"),
             TokenType::Star => write!(f, "STAR"),
+            TokenType::Percent => write!(f, "PERCENT"),
+            TokenType::Amp => write!(f, "AMP"),
+            TokenType::Pipe => write!(f, "PIPE"),
+            TokenType::Caret => write!(f, "CARET"),
+            TokenType::Tilde => write!(f, "TILDE"),
+            TokenType::Question => write!(f, "QUESTION"),
+            TokenType::Colon => write!(f, "COLON"),
             TokenType::Bang => write!(f, "BANG"),
             TokenType::Equal => write!(f, "EQUAL"),
+            TokenType::StarStar => write!(f, "STAR_STAR"),
+            TokenType::PlusPlus => write!(f, "PLUS_PLUS"),
+            TokenType::MinusMinus => write!(f, "MINUS_MINUS"),
+            TokenType::PlusEqual => write!(f, "PLUS_EQ"),
+            TokenType::MinusEqual => write!(f, "MINUS_EQ"),
+            TokenType::StarEqual => write!(f, "STAR_EQ"),
+            TokenType::SlashEqual => write!(f, "SLASH_EQ"),
+            TokenType::PercentEqual => write!(f, "PERCENT_EQ"),
+            TokenType::QuestionQuestion => write!(f, "QUESTION_QUESTION"),
+            TokenType::QuestionDot => write!(f, "QUESTION_DOT"),
+            TokenType::FatArrow => write!(f, "FAT_ARROW"),
+            TokenType::DotDot => write!(f, "DOT_DOT"),
+            TokenType::Ellipsis => write!(f, "ELLIPSIS"),
             TokenType::BangEqual => write!(f, "BANG_EQ"),
             TokenType::EqualEqual => write!(f, "EQ_EQ"),
             TokenType::Greater => write!(f, "GREATER"),
             TokenType::GreaterEqual => write!(f, "GREATER_EQ"),
+            TokenType::GreaterGreater => write!(f, "GREATER_GREATER"),
             TokenType::Less => write!(f, "LESS"),
             TokenType::LessEqual => write!(f, "LESS_EQ"),
+            TokenType::LessLess => write!(f, "LESS_LESS"),
             TokenType::Ident => write!(f, "IDENT"),
+            TokenType::Private => write!(f, "PRIVATE"),
             TokenType::StringLiteral => write!(f, "STRING"),
+            TokenType::Interpolation => write!(f, "INTERPOLATION"),
             TokenType::Number => write!(f, "NUM"),
             TokenType::And => write!(f, "AND"),
             TokenType::Class => write!(f, "CLASS"),
@@ -218,6 +369,16 @@ impl std::fmt::Display for TokenType {
             TokenType::Var => write!(f, "VAR"),
             TokenType::While => write!(f, "WHILE"),
             TokenType::Break => write!(f, "BREAK"),
+            TokenType::Match => write!(f, "MATCH"),
+            TokenType::In => write!(f, "IN"),
+            TokenType::Continue => write!(f, "CONTINUE"),
+            TokenType::Yield => write!(f, "YIELD"),
+            TokenType::Const => write!(f, "CONST"),
+            TokenType::Let => write!(f, "LET"),
+            TokenType::Trait => write!(f, "TRAIT"),
+            TokenType::Enum => write!(f, "ENUM"),
+            TokenType::With => write!(f, "WITH"),
+            TokenType::Abstract => write!(f, "ABSTRACT"),
             TokenType::Eof => write!(f, "EOF"),
         }
     }
@@ -5,38 +5,110 @@ const OUTPUT_DIR: &str = "tests/output";
 
 macro_rules! test_case {
     ($name:ident, $input:expr) => {
+        test_case!($name, $input, &[]);
+    };
+    // flags are passed to rlox before the script
+    ($name:ident, $input:expr, $flags:expr) => {
         #[test]
         fn $name() {
-            run_master($input)
+            run_master($input, $flags);
         }
     };
 }
 
-fn run_master(input: &str) {
+// returns what rlox wrote to stderr
+fn run_master(input: &str, flags: &[&str]) -> String {
     let in_file: PathBuf = [INPUT_DIR, input].iter().collect();
 
-    let output = input.to_string() + &".out";
+    let output = input.to_string() + ".out";
     let out_file: PathBuf = [OUTPUT_DIR, &output].iter().collect();
 
+    // errors are only checked if the test has a .err file
+    let errors = input.to_string() + ".err";
+    let err_file: PathBuf = [OUTPUT_DIR, &errors].iter().collect();
+
     let expected = std::fs::read_to_string(&out_file).expect("Failed to read file");
 
-    let actual = Command::new("cargo")
-        .args(&["run", in_file.as_path().to_str().unwrap()])
+    // the binary built with the tests, so --release tests run an optimized one
+    let actual = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(flags)
+        .arg(in_file.as_path().to_str().unwrap())
         .output()
-        .expect("Failed to execute process")
-        .stdout;
-    let actual = String::from_utf8(actual).expect("Failed to convert to string");
+        .expect("Failed to execute process");
+    let stdout = String::from_utf8(actual.stdout).expect("Failed to convert to string");
+
+    assert_eq!(&expected, &stdout);
 
-    assert_eq!(&expected, &actual);
+    let stderr = String::from_utf8(actual.stderr).expect("Failed to convert to string");
+    if let Ok(expected) = std::fs::read_to_string(&err_file) {
+        assert_eq!(&expected, &stderr);
+    }
+
+    stderr
 }
 
+test_case!(abstract_methods, "abstract.lox");
+test_case!(abstract_err, "abstract_err.lox");
 test_case!(brk, "break.lox");
 test_case!(class, "class.lox");
+test_case!(compound, "compound.lox");
+test_case!(conditional, "conditional.lox");
+test_case!(constants, "const.lox");
+test_case!(constants_err, "const_err.lox");
 test_case!(counter, "counter.lox");
+test_case!(cycles, "cycles.lox");
+test_case!(destructure, "destructure.lox");
 test_case!(expr, "expr.lox");
+test_case!(freeze, "freeze.lox");
 test_case!(function, "function.lox");
+test_case!(generators, "generators.lox");
+test_case!(integers, "integers.lox");
+test_case!(iterators, "iterators.lox");
 test_case!(inheritance, "inheritance.lox");
 test_case!(lambda, "lambda.lox");
 test_case!(loops, "loops.lox");
+test_case!(matches, "match.lox");
+test_case!(numbers, "numbers.lox");
+test_case!(numbers_err, "numbers_err.lox");
+test_case!(operators, "operators.lox");
+test_case!(params, "params.lox");
+test_case!(params_err, "params_err.lox");
+test_case!(private, "private.lox");
+test_case!(private_err, "private_err.lox");
+test_case!(fields, "fields.lox");
+test_case!(reflection, "reflection.lox");
+test_case!(enums, "enums.lox");
+test_case!(tail_calls, "tail_calls.lox");
+test_case!(optimize, "optimize.lox");
+test_case!(optimize_disabled, "optimize.lox", &["--no-optimize"]);
+test_case!(shapes, "shapes.lox");
 test_case!(scopes, "scopes.lox");
+test_case!(shifts_err, "shifts_err.lox");
 test_case!(stmts, "stmts.lox");
+test_case!(strings, "strings.lox");
+test_case!(strings_err, "strings_err.lox");
+test_case!(traits, "traits.lox");
+
+// a million calls and list nodes take minutes unoptimized; run with --release
+#[test]
+#[cfg_attr(debug_assertions, ignore)]
+fn tail_calls_deep() {
+    run_master("tail_calls_deep.lox", &[]);
+}
+
+// durations vary, so only check that every phase is reported
+#[test]
+fn timed() {
+    let stderr = run_master("class.lox", &["--time"]);
+
+    for phase in ["scan", "parse", "resolve", "optimize", "execute"] {
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.starts_with(phase) && line.ends_with(" ms")),
+            "no {} time in {:?}",
+            phase,
+            stderr
+        );
+    }
+}